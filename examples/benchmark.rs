@@ -1,7 +1,8 @@
 #![feature(slice_bytes)]
 
 /// See how quickly we can send 1,000,000 round trip pingpongs
-/// in a purely serial fashion
+/// in a purely serial fashion, first with every Context reregistered after every
+/// event, as the reactor used to do, then only when its interest changes
 extern crate time;
 extern crate reactor;
 extern crate env_logger;
@@ -14,9 +15,12 @@ use reactor::{ReactorCtrl,
               ConnResult,
               Evented,
               EventSet,
+              PollOpt,
               Context,
               EventType,
-              Token};
+              Token,
+              ReactorStats,
+              SYSTEM_RESOLVER};
 
 use reactor::tcp::{TcpStream};
 
@@ -28,6 +32,7 @@ struct EchoConn {
     token : Token,
     count : u32,
     start_time : u64,
+    poll_opt : PollOpt
 }
 
 impl Context for EchoConn {
@@ -80,37 +85,43 @@ impl Context for EchoConn {
     fn get_interest(&self) -> EventSet {
         self.interest
     }
-}
 
-fn main() {
-    env_logger::init().unwrap();
+    // only one message is read per event, so ask to hear again while more is waiting
+    fn get_poll_opt(&self) -> PollOpt {
+        self.poll_opt
+    }
+}
 
+/// Run the pingpong test over a connection on port, with both ends registered with
+/// poll_opt, and return the reactor's counters
+fn pingpong(port : u16, poll_opt : PollOpt) -> ReactorStats {
     let mut client : Option<Token> = None;
     let mut server : Option<Token> = None;
     let mut r = Reactor::new();
 
-    let _ltoken = r.listen("127.0.0.1:10000", Box::new(|res, ctrl| {
+    let _ltoken = r.listen(("127.0.0.1", port), Box::new(|res, ctrl| {
         match res {
             ConnResult::Connected(sock, tok, addr) => {
                 println!("Connection request from {}", addr);
                 server = Some(tok);
 
                 //We've received a connection. Initiate PINGPONG protocol in 1 second
-                ctrl.timeout_conn(1000, tok).unwrap();
+                ctrl.timeout_conn(1000, tok, 0).unwrap();
 
                 Some(Box::new(EchoConn {
                                 interest: EventSet::readable(),
                                 token: tok.clone(),
                                 sock: sock,
                                 count: 0,
-                                start_time: 0}))
+                                start_time: 0,
+                                poll_opt: poll_opt}))
             },
             _ => {panic!("We shouldn't be here")}
         }
     })).unwrap();
 
     println!("Connecting to localhost");
-    r.connect("localhost", 10000, Box::new(|res, _ctrl| {
+    r.connect("localhost", port, SYSTEM_RESOLVER, Box::new(|res, _ctrl| {
         match res {
             ConnResult::Connected(sock, tok, addr) => {
                 println!("Completing connection to {}", addr);
@@ -120,14 +131,27 @@ fn main() {
                                 token: tok.clone(),
                                 sock: sock,
                                 count: 0,
-                                start_time: 0}))
+                                start_time: 0,
+                                poll_opt: poll_opt}))
             },
-            ConnResult::Failed(err) => {panic!("Failed to connect to localhost:10000 error: {}", err)}
+            ConnResult::Failed(err) => {panic!("Failed to connect to localhost:{} error: {}", port, err)}
         }
     })).unwrap();
 
     r.run();
+    r.stats()
+}
+
+fn main() {
+    env_logger::init().unwrap();
+
+    // a oneshot registration has to be re-armed after every event, which is the
+    // epoll_ctl call the reactor used to make for every Context
+    let before = pingpong(10000, PollOpt::level() | PollOpt::oneshot());
+    let after = pingpong(10001, PollOpt::level());
 
+    println!("epoll_ctl reregistrations: {} before, {} after, {} saved",
+             before.reregisters, after.reregisters, after.reregisters_saved);
 }
 
 
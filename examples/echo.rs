@@ -17,9 +17,12 @@ use reactor::{ReactorCtrl,
               ConnResult,
               Evented,
               EventSet,
+              PollOpt,
               Context,
               EventType,
-              Token};
+              Schedule,
+              Token,
+              SYSTEM_RESOLVER};
 
 use reactor::tcp::{TcpStream};
 
@@ -48,10 +51,7 @@ impl Context for EchoConn {
                     else if msg == "PONG!" {
                         println!("Successfully completed PING/PONG");
                         self.count += 1;
-                        if self.count < 3 {
-                            ctrl.timeout_conn(1000, self.token).unwrap();
-                        }
-                        else {
+                        if self.count >= 3 {
                             ctrl.shutdown();
                         }
                     }
@@ -77,6 +77,11 @@ impl Context for EchoConn {
     fn get_interest(&self) -> EventSet {
         self.interest
     }
+
+    // only one message is read per event, so ask to hear again while more is waiting
+    fn get_poll_opt(&self) -> PollOpt {
+        PollOpt::level()
+    }
 }
 
 fn main() {
@@ -92,8 +97,8 @@ fn main() {
                 println!("Connection request from {}", addr);
                 server = Some(tok);
 
-                //We've received a connection. Initiate PINGPONG protocol in 1 second
-                ctrl.timeout_conn(1000, tok).unwrap();
+                //We've received a connection. Send a PING every second
+                ctrl.interval_conn(1000, Schedule::FixedDelay, tok, 0).unwrap();
 
                 Some(Box::new(EchoConn {
                                 interest: EventSet::readable(),
@@ -106,7 +111,7 @@ fn main() {
     })).unwrap();
 
     println!("Connecting to localhost");
-    r.connect("localhost", 10000, Box::new(|res, _ctrl| {
+    r.connect("localhost", 10000, SYSTEM_RESOLVER, Box::new(|res, _ctrl| {
         match res {
             ConnResult::Connected(sock, tok, addr) => {
                 println!("Completing connection to {}", addr);
@@ -0,0 +1,148 @@
+/// This example runs a small echo server and a connection pool of clients to it on the same
+/// event loop. Eight requests are made through a pool of between 2 and 4 connections, so
+/// some of them have to wait for a connection to be checked back in. Once every request
+/// has been answered the example shuts itself down.
+
+extern crate reactor;
+extern crate tendril;
+extern crate env_logger;
+
+use std::cell::{Cell, RefCell};
+use std::rc::Rc;
+use std::io::{Read, Write};
+use tendril::{Tendril, Atomic};
+use tendril::fmt::Bytes;
+use reactor::{ReactorCtrl,
+              Reactor,
+              ConnResult,
+              ConnPool,
+              PoolConfig,
+              Evented,
+              EventSet,
+              PollOpt,
+              Context,
+              EventType,
+              Token,
+              SYSTEM_RESOLVER};
+
+use reactor::tcp::{TcpStream};
+
+const REQUESTS : u32 = 8;
+
+struct EchoServ {
+    sock : TcpStream
+}
+
+impl Context for EchoServ {
+
+    fn on_event(&mut self, ctrl : &mut ReactorCtrl, evt : EventType) {
+        match evt {
+            EventType::Readable => {
+                let mut buf : [u8; 5] = [0; 5];
+                if let Ok(5) = self.sock.read(&mut buf) {
+                    self.sock.write(&buf).unwrap();
+                }
+            },
+            EventType::Disconnect => { ctrl.close_current().unwrap(); },
+            _ => {}
+        }
+    }
+
+    fn get_evented(&self) -> &Evented {
+        &self.sock as &Evented
+    }
+
+    fn get_interest(&self) -> EventSet {
+        EventSet::readable()
+    }
+
+    fn get_poll_opt(&self) -> PollOpt {
+        PollOpt::level()
+    }
+}
+
+struct PoolConn {
+    sock : TcpStream,
+    token : Token,
+    pool : Rc<RefCell<Option<ConnPool>>>,
+    answered : Rc<Cell<u32>>
+}
+
+impl Context for PoolConn {
+
+    fn on_event(&mut self, ctrl : &mut ReactorCtrl, evt : EventType) {
+        match evt {
+            EventType::Notify(buf) => {
+                self.sock.write(&buf).unwrap();
+            },
+            EventType::Readable => {
+                let mut buf : [u8; 5] = [0; 5];
+                if let Ok(5) = self.sock.read(&mut buf) {
+                    println!("{:?} answered {}", self.token, String::from_utf8_lossy(&buf));
+                    let pool = self.pool.borrow().clone().unwrap();
+                    pool.checkin(ctrl, self.token);
+
+                    self.answered.set(self.answered.get() + 1);
+                    if self.answered.get() == REQUESTS {
+                        let (idle, busy, connecting) = pool.size();
+                        println!("All answered, pool has {} idle, {} busy, {} connecting", idle, busy, connecting);
+                        ctrl.shutdown();
+                    }
+                }
+            },
+            _ => {}
+        }
+    }
+
+    fn get_evented(&self) -> &Evented {
+        &self.sock as &Evented
+    }
+
+    fn get_interest(&self) -> EventSet {
+        EventSet::readable()
+    }
+
+    fn get_poll_opt(&self) -> PollOpt {
+        PollOpt::level()
+    }
+}
+
+fn main() {
+    env_logger::init().unwrap();
+
+    let mut r = Reactor::new();
+
+    r.listen("127.0.0.1:10001", Box::new(|res, _ctrl| {
+        match res {
+            ConnResult::Connected(sock, _tok, _addr) => Some(Box::new(EchoServ { sock: sock })),
+            _ => {panic!("We shouldn't be here")}
+        }
+    })).unwrap();
+
+    // the pool's Contexts check themselves back in, so they need the pool, which
+    // can only be filled in once it has been created
+    let slot : Rc<RefCell<Option<ConnPool>>> = Rc::new(RefCell::new(None));
+    let answered = Rc::new(Cell::new(0));
+
+    let (s, a) = (slot.clone(), answered.clone());
+    let pool = ConnPool::new(&mut r.ctrl(), "127.0.0.1", 10001, SYSTEM_RESOLVER, PoolConfig::new(2, 4),
+                             Box::new(move |sock, tok, _ctrl| {
+        println!("Pool opened {:?}", tok);
+        Some(Box::new(PoolConn { sock: sock, token: tok, pool: s.clone(), answered: a.clone() }))
+    })).unwrap();
+    *slot.borrow_mut() = Some(pool.clone());
+
+    r.timeout(100, Box::new(move |_, ctrl| {
+        let notifier = ctrl.channel();
+        for i in 0 .. REQUESTS {
+            let notifier = notifier.clone();
+            pool.checkout(ctrl, Box::new(move |res, _ctrl| {
+                let tok = res.unwrap();
+                println!("Request {} got {:?}", i, tok);
+                notifier.send((tok, Tendril::<Bytes, Atomic>::from_slice(&b"PING!"[..]))).unwrap();
+            }));
+        }
+    })).unwrap();
+
+    r.run();
+}
@@ -0,0 +1,144 @@
+//! Compare draining an OutQueue of many small frames into a mio socket with one
+//! write per frame, with write_vectored on the socket itself, which mio leaves to
+//! the default of writing one buffer per call, and with real vectored writes through
+//! `Writev`. A thread on the other end of a loopback connection reads everything,
+//! and the queue is drained again whenever the socket would block, so only the
+//! number of write calls differs between the runs.
+extern crate time;
+extern crate reactor;
+extern crate tendril;
+
+use std::io::{self, Read, Write, IoSlice};
+use std::net::{self, TcpListener};
+use std::os::unix::io::{FromRawFd, IntoRawFd};
+use std::thread;
+use tendril::{Tendril, Atomic};
+use tendril::fmt::Bytes;
+use reactor::tcp::TcpStream;
+use reactor::utils::{OutQueue, Writev};
+
+use time::{precise_time_ns};
+
+const FRAMES : usize = 1000;
+const FRAME_SIZE : usize = 64;
+const ROUNDS : usize = 1000;
+
+/// How the queue is drained into the socket
+#[derive(Clone, Copy, Debug)]
+enum Mode {
+    /// one write per frame
+    Unvectored,
+    /// write_vectored on the mio socket as it is
+    Default,
+    /// write_vectored through Writev
+    Writev
+}
+
+/// Count the calls made to the socket underneath
+struct Counting<'a, W : 'a> {
+    sock : &'a mut W,
+    calls : usize
+}
+
+impl<'a, W : Write> Write for Counting<'a, W> {
+    fn write(&mut self, buf : &[u8]) -> io::Result<usize> {
+        self.calls += 1;
+        self.sock.write(buf)
+    }
+
+    fn write_vectored(&mut self, bufs : &[IoSlice]) -> io::Result<usize> {
+        self.calls += 1;
+        self.sock.write_vectored(bufs)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.sock.flush()
+    }
+}
+
+/// Drain the whole queue into sock, returning the write calls it took
+fn drain_all(q : &mut OutQueue, sock : &mut TcpStream, mode : Mode) -> usize {
+    let mut calls = 0;
+    while !q.is_empty() {
+        calls += match mode {
+            Mode::Writev => drain_counted(q, &mut Writev(&mut *sock)),
+            _ => drain_counted(q, &mut *sock)
+        };
+    }
+    calls
+}
+
+/// Drain as much of the queue as sock will take, returning the write calls it took
+fn drain_counted<W : Write>(q : &mut OutQueue, sock : &mut W) -> usize {
+    let mut w = Counting { sock: sock, calls: 0 };
+    q.drain(&mut w).unwrap();
+    w.calls
+}
+
+fn run(mode : Mode) {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    let reader = thread::spawn(move || {
+        let (mut sock, _) = listener.accept().unwrap();
+        let mut buf = [0u8; 65536];
+        let mut total = 0;
+        loop {
+            match sock.read(&mut buf) {
+                Ok(0) => return total,
+                Ok(n) => total += n,
+                Err(e) => panic!("read failed: {}", e)
+            }
+        }
+    });
+
+    // connected before it is made non-blocking, as a mio socket from the reactor would be
+    let std_sock = net::TcpStream::connect(addr).unwrap();
+    std_sock.set_nonblocking(true).unwrap();
+    let mut sock = unsafe { TcpStream::from_raw_fd(std_sock.into_raw_fd()) };
+    let frame = Tendril::<Bytes, Atomic>::from_slice(&[b'x'; FRAME_SIZE][..]);
+    let mut q = OutQueue::with_water_marks(usize::max_value(), usize::max_value());
+    match mode {
+        Mode::Unvectored => {},
+        _ => q.set_vectored(true)
+    }
+
+    let mut calls = 0;
+    let start = precise_time_ns();
+    for _ in 0 .. ROUNDS {
+        // queue the frames first, as they would be while waiting for a writable event
+        for _ in 0 .. FRAMES {
+            queue(&mut q, frame.clone());
+        }
+        calls += drain_all(&mut q, &mut sock, mode);
+    }
+    let elapsed = precise_time_ns() - start;
+    drop(sock);
+
+    let total = reader.join().unwrap();
+    assert_eq!(total, FRAMES * FRAME_SIZE * ROUNDS);
+    println!("{:?}: {} bytes in {} write calls, {} ms", mode, total, calls, elapsed / 1000000);
+}
+
+/// Queue a frame without trying to write it, by handing the queue a socket which
+/// would always block
+fn queue(q : &mut OutQueue, frame : Tendril<Bytes, Atomic>) {
+    q.write(frame, &mut Full).unwrap();
+}
+
+struct Full;
+
+impl Write for Full {
+    fn write(&mut self, _buf : &[u8]) -> io::Result<usize> {
+        Err(io::Error::new(io::ErrorKind::WouldBlock, "full"))
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+fn main() {
+    run(Mode::Unvectored);
+    run(Mode::Default);
+    run(Mode::Writev);
+}
@@ -0,0 +1,522 @@
+use std::io::{self, Error, ErrorKind};
+
+use mio::{EventSet, Evented, PollOpt};
+use mio::tcp::TcpStream;
+use tendril::{Tendril, Atomic};
+use tendril::fmt::Bytes;
+
+use context::{Context, EventType};
+use reactor_ctrl::ReactorCtrl;
+use utils::{ReadBuf, OutQueue, Flow};
+#[cfg(unix)]
+use utils::Writev;
+
+/// Splits the bytes read from a socket into messages, and turns messages into bytes
+/// to be written to it
+pub trait Codec {
+    /// The messages decoded from the socket
+    type In;
+    /// The messages encoded for the socket
+    type Out;
+
+    /// Decode the next message from the front of the buffer, consuming its bytes.
+    /// Returns None if the buffer does not yet hold a whole message. An error is
+    /// passed to `FramedHandler::on_decode_error`; decoding can only go on after it
+    /// if the bytes of the bad message were consumed.
+    fn decode(&mut self, buf : &mut ReadBuf) -> io::Result<Option<Self::In>>;
+
+    /// Encode a message, ready to be written to the socket
+    fn encode(&mut self, msg : Self::Out) -> io::Result<Tendril<Bytes, Atomic>>;
+}
+
+/// The user half of a FramedContext, which is handed whole messages
+pub trait FramedHandler<C : Codec> {
+    /// A message has been decoded, replies can be sent with `conn.send`
+    fn on_message(&mut self, conn : &mut Framed<C>, ctrl : &mut ReactorCtrl, msg : C::In);
+
+    /// Any event besides reading and writing, which the FramedContext looks after:
+    /// Timeout, Message and Disconnect. The connection is closed after Disconnect.
+    fn on_event(&mut self, _conn : &mut Framed<C>, _ctrl : &mut ReactorCtrl, _evt : EventType) {}
+
+    /// The peer has caught up after `send` returned `Flow::Pause`, so sending may
+    /// start again
+    fn on_resume(&mut self, _conn : &mut Framed<C>, _ctrl : &mut ReactorCtrl) {}
+
+    /// A message could not be decoded. Unless the connection is closed, which is
+    /// what happens by default, decoding goes on with the next message
+    fn on_decode_error(&mut self, conn : &mut Framed<C>, ctrl : &mut ReactorCtrl, err : io::Error) {
+        error!("Failed to decode a message from {:?}: {}", ctrl.current(), err);
+        conn.close(ctrl);
+    }
+}
+
+/// The socket of a FramedContext with its buffers and codec
+pub struct Framed<C : Codec> {
+    sock : TcpStream,
+    codec : C,
+    rbuf : ReadBuf,
+    wbuf : OutQueue,
+    interest : EventSet,
+    closed : bool
+}
+
+impl<C : Codec> Framed<C> {
+
+    /// Encode and write a message, queueing whatever cannot be written yet and
+    /// asking for writable events until the queue is drained. `Flow::Pause` says
+    /// the peer is not keeping up, and more should not be sent until it is
+    pub fn send(&mut self, ctrl : &mut ReactorCtrl, msg : C::Out) -> io::Result<Flow> {
+        let buf = try!(self.codec.encode(msg));
+        self.send_bytes(ctrl, buf)
+    }
+
+    /// Write bytes which are already encoded
+    pub fn send_bytes(&mut self, ctrl : &mut ReactorCtrl, buf : Tendril<Bytes, Atomic>) -> io::Result<Flow> {
+        let flow = try!(self.wbuf.write(buf, &mut self.sock));
+        if flow != Flow::Done {
+            self.want_writable(ctrl, true);
+        }
+        Ok(flow)
+    }
+
+    /// Close the connection once the current event has been handled, nothing more
+    /// is read from it or handed to the handler
+    pub fn close(&mut self, ctrl : &mut ReactorCtrl) {
+        if self.closed {
+            return;
+        }
+        self.closed = true;
+        if let Err(e) = ctrl.close_current() {
+            debug!("Failed to close framed connection: {}", e);
+        }
+    }
+
+    pub fn is_closed(&self) -> bool {
+        self.closed
+    }
+
+    /// Whether the peer has fallen behind, see `OutQueue::is_paused`
+    pub fn is_paused(&self) -> bool {
+        self.wbuf.is_paused()
+    }
+
+    pub fn get_ref(&self) -> &TcpStream {
+        &self.sock
+    }
+
+    pub fn codec_mut(&mut self) -> &mut C {
+        &mut self.codec
+    }
+
+    fn want_writable(&mut self, ctrl : &mut ReactorCtrl, writable : bool) {
+        if self.interest.is_writable() == writable {
+            return;
+        }
+        if writable {
+            self.interest.insert(EventSet::writable());
+        }
+        else {
+            self.interest.remove(EventSet::writable());
+        }
+        if let Some(token) = ctrl.current() {
+            if let Err(e) = ctrl.set_interest(token, self.interest) {
+                debug!("Failed to change interest for {:?}: {}", token, e);
+            }
+        }
+    }
+}
+
+/// A Context which reads and writes whole messages through a Codec, so that a
+/// protocol only has to say how its frames are laid out and what to do with them.
+/// Reads are buffered until a whole message has arrived, and writes until the
+/// socket will take them. Buffers sent to the connection with a Notifier are taken
+/// to be encoded already, and are written as they are.
+///
+///```ignore
+/// struct Echo;
+///
+/// impl FramedHandler<LineCodec> for Echo {
+///     fn on_message(&mut self, conn : &mut Framed<LineCodec>, ctrl : &mut ReactorCtrl, line : Tendril<Bytes, Atomic>) {
+///         conn.send(ctrl, line).unwrap();
+///     }
+/// }
+///
+/// r.listen("127.0.0.1:10000", Box::new(|res, _ctrl| {
+///     match res {
+///         ConnResult::Connected(sock, _tok, _addr) => Some(Box::new(FramedContext::new(sock, LineCodec::new(), Echo))),
+///         _ => None
+///     }
+/// })).unwrap();
+///```
+pub struct FramedContext<C : Codec, H : FramedHandler<C>> {
+    conn : Framed<C>,
+    handler : H
+}
+
+impl<C : Codec, H : FramedHandler<C>> FramedContext<C, H> {
+
+    /// Frame this socket with buffers of the default sizes
+    pub fn new(sock : TcpStream, codec : C, handler : H) -> FramedContext<C, H> {
+        let mut wbuf = OutQueue::new();
+        #[cfg(unix)]
+        wbuf.set_vectored(true);
+        FramedContext::with_buffers(sock, codec, handler, ReadBuf::new(), wbuf)
+    }
+
+    /// Frame this socket with the given buffers, whose sizes bound how much
+    /// may be queued either way. On unix the socket is drained through `Writev`,
+    /// so the write queue may have vectored writes turned on
+    pub fn with_buffers(sock : TcpStream, codec : C, handler : H, rbuf : ReadBuf, wbuf : OutQueue) -> FramedContext<C, H> {
+        FramedContext {
+            conn: Framed {
+                sock: sock,
+                codec: codec,
+                rbuf: rbuf,
+                wbuf: wbuf,
+                interest: EventSet::readable(),
+                closed: false
+            },
+            handler: handler
+        }
+    }
+
+    pub fn get_ref(&self) -> &Framed<C> {
+        &self.conn
+    }
+
+    pub fn get_mut(&mut self) -> &mut Framed<C> {
+        &mut self.conn
+    }
+
+    pub fn handler_mut(&mut self) -> &mut H {
+        &mut self.handler
+    }
+
+    fn on_readable(&mut self, ctrl : &mut ReactorCtrl) {
+        if let Err(e) = self.conn.rbuf.read_from(&mut self.conn.sock) {
+            debug!("Failed to read from {:?}: {}", ctrl.current(), e);
+            return self.disconnect(ctrl);
+        }
+        loop {
+            let len = self.conn.rbuf.len();
+            match self.conn.codec.decode(&mut self.conn.rbuf) {
+                Ok(Some(msg)) => self.handler.on_message(&mut self.conn, ctrl, msg),
+                Ok(None) => break,
+                Err(e) => {
+                    self.handler.on_decode_error(&mut self.conn, ctrl, e);
+                    // the same bytes would only fail again
+                    if !self.conn.closed && self.conn.rbuf.len() == len {
+                        debug!("Codec consumed nothing of a bad message from {:?}, closing", ctrl.current());
+                        self.conn.close(ctrl);
+                    }
+                }
+            }
+            if self.conn.closed {
+                return;
+            }
+        }
+        if self.conn.rbuf.is_eof() {
+            self.disconnect(ctrl);
+        }
+    }
+
+    fn on_writable(&mut self, ctrl : &mut ReactorCtrl) {
+        #[cfg(unix)]
+        let res = self.conn.wbuf.drain(&mut Writev(&mut self.conn.sock));
+        #[cfg(not(unix))]
+        let res = self.conn.wbuf.drain(&mut self.conn.sock);
+        match res {
+            Ok(flow) => {
+                if self.conn.wbuf.is_empty() {
+                    self.conn.want_writable(ctrl, false);
+                }
+                if flow == Flow::Resume {
+                    self.handler.on_resume(&mut self.conn, ctrl);
+                }
+            },
+            Err(e) => {
+                debug!("Failed to write to {:?}: {}", ctrl.current(), e);
+                self.disconnect(ctrl);
+            }
+        }
+    }
+
+    /// Let the handler know, once, then close the connection
+    fn disconnect(&mut self, ctrl : &mut ReactorCtrl) {
+        if self.conn.closed {
+            return;
+        }
+        self.handler.on_event(&mut self.conn, ctrl, EventType::Disconnect);
+        self.conn.close(ctrl);
+    }
+}
+
+impl<C : Codec, H : FramedHandler<C>> Context for FramedContext<C, H> {
+
+    fn on_event(&mut self, ctrl : &mut ReactorCtrl, evt : EventType) {
+        if self.conn.closed {
+            return;
+        }
+        match evt {
+            EventType::Readable => self.on_readable(ctrl),
+            EventType::Writable => self.on_writable(ctrl),
+            EventType::Disconnect => self.disconnect(ctrl),
+            EventType::Notify(buf) => {
+                if let Err(e) = self.conn.send_bytes(ctrl, buf) {
+                    debug!("Failed to write to {:?}: {}", ctrl.current(), e);
+                    self.disconnect(ctrl);
+                }
+            },
+            evt => self.handler.on_event(&mut self.conn, ctrl, evt)
+        }
+    }
+
+    fn get_evented(&self) -> &Evented {
+        &self.conn.sock as &Evented
+    }
+
+    fn get_interest(&self) -> EventSet {
+        self.conn.interest
+    }
+
+    fn get_poll_opt(&self) -> PollOpt {
+        PollOpt::edge()
+    }
+}
+
+/// Messages separated by `\n`, handed out without it, or without `\r\n`
+pub struct LineCodec;
+
+impl LineCodec {
+    pub fn new() -> LineCodec {
+        LineCodec
+    }
+}
+
+impl Codec for LineCodec {
+    type In = Tendril<Bytes, Atomic>;
+    type Out = Tendril<Bytes, Atomic>;
+
+    fn decode(&mut self, buf : &mut ReadBuf) -> io::Result<Option<Tendril<Bytes, Atomic>>> {
+        let end = match buf.peek().iter().position(|&b| b == b'\n') {
+            Some(end) => end,
+            None => return Ok(None)
+        };
+        let len = if end > 0 && buf.peek()[end - 1] == b'\r' { end - 1 } else { end };
+        let line = buf.take(len);
+        buf.consume(end + 1 - len);
+        Ok(Some(line))
+    }
+
+    /// Appends a `\n`, the message should not hold one of its own
+    fn encode(&mut self, msg : Tendril<Bytes, Atomic>) -> io::Result<Tendril<Bytes, Atomic>> {
+        let mut buf = msg;
+        buf.push_slice(b"\n");
+        Ok(buf)
+    }
+}
+
+/// The size of the length which a LengthPrefixedCodec puts before each message
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Width {
+    U16,
+    U32
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Endian {
+    Big,
+    Little
+}
+
+/// Messages preceded by their length in bytes, not counting the length itself
+pub struct LengthPrefixedCodec {
+    width : Width,
+    endian : Endian
+}
+
+impl LengthPrefixedCodec {
+    pub fn new(width : Width, endian : Endian) -> LengthPrefixedCodec {
+        LengthPrefixedCodec {
+            width: width,
+            endian: endian
+        }
+    }
+
+    fn header_len(&self) -> usize {
+        match self.width {
+            Width::U16 => 2,
+            Width::U32 => 4
+        }
+    }
+}
+
+impl Codec for LengthPrefixedCodec {
+    type In = Tendril<Bytes, Atomic>;
+    type Out = Tendril<Bytes, Atomic>;
+
+    fn decode(&mut self, buf : &mut ReadBuf) -> io::Result<Option<Tendril<Bytes, Atomic>>> {
+        let header = self.header_len();
+        if buf.len() < header {
+            return Ok(None);
+        }
+        let len = {
+            let bytes = &buf.peek()[.. header];
+            match self.endian {
+                Endian::Big => bytes.iter().fold(0, |len, &b| (len << 8) | b as usize),
+                Endian::Little => bytes.iter().rev().fold(0, |len, &b| (len << 8) | b as usize)
+            }
+        };
+        if buf.len() < header + len {
+            return Ok(None);
+        }
+        buf.consume(header);
+        Ok(Some(buf.take(len)))
+    }
+
+    fn encode(&mut self, msg : Tendril<Bytes, Atomic>) -> io::Result<Tendril<Bytes, Atomic>> {
+        let header = self.header_len();
+        let max = match self.width {
+            Width::U16 => 0xffff,
+            Width::U32 => 0xffff_ffff
+        };
+        if msg.len() > max {
+            return Err(Error::new(ErrorKind::InvalidInput, "message is too long for its length prefix"));
+        }
+        let mut bytes = [0u8; 4];
+        for i in 0 .. header {
+            let shift = match self.endian {
+                Endian::Big => 8 * (header - 1 - i),
+                Endian::Little => 8 * i
+            };
+            bytes[i] = (msg.len() >> shift) as u8;
+        }
+        let mut buf : Tendril<Bytes, Atomic> = Tendril::from_slice(&bytes[.. header]);
+        buf.push_slice(&msg);
+        Ok(buf)
+    }
+}
+
+/// Messages which are all the same number of bytes
+pub struct FixedSizeCodec {
+    size : usize
+}
+
+impl FixedSizeCodec {
+    pub fn new(size : usize) -> FixedSizeCodec {
+        FixedSizeCodec {
+            size: size
+        }
+    }
+}
+
+impl Codec for FixedSizeCodec {
+    type In = Tendril<Bytes, Atomic>;
+    type Out = Tendril<Bytes, Atomic>;
+
+    fn decode(&mut self, buf : &mut ReadBuf) -> io::Result<Option<Tendril<Bytes, Atomic>>> {
+        if buf.len() < self.size {
+            return Ok(None);
+        }
+        Ok(Some(buf.take(self.size)))
+    }
+
+    fn encode(&mut self, msg : Tendril<Bytes, Atomic>) -> io::Result<Tendril<Bytes, Atomic>> {
+        if msg.len() != self.size {
+            return Err(Error::new(ErrorKind::InvalidInput, "message is not the codec's size"));
+        }
+        Ok(msg)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tendril::{Tendril, Atomic};
+    use tendril::fmt::Bytes;
+    use utils::ReadBuf;
+    use super::{Codec, LineCodec, LengthPrefixedCodec, FixedSizeCodec, Width, Endian};
+
+    /// A ReadBuf holding bytes
+    fn filled(bytes : &[u8]) -> ReadBuf {
+        let mut buf = ReadBuf::new();
+        buf.read_from(&mut &bytes[..]).unwrap();
+        buf
+    }
+
+    fn bytes(b : &[u8]) -> Tendril<Bytes, Atomic> {
+        Tendril::from_slice(b)
+    }
+
+    /// Every message codec decodes from bytes, leaving the rest in the buffer
+    fn decode_all<C : Codec<In = Tendril<Bytes, Atomic>>>(codec : &mut C, buf : &mut ReadBuf) -> Vec<Vec<u8>> {
+        let mut msgs = Vec::new();
+        while let Some(msg) = codec.decode(buf).unwrap() {
+            msgs.push(msg.to_vec());
+        }
+        msgs
+    }
+
+    #[test]
+    fn line_strips_lf_and_crlf() {
+        let mut buf = filled(b"one\r\ntwo\n\r\n\nthree\r");
+        let msgs = decode_all(&mut LineCodec::new(), &mut buf);
+        assert_eq!(msgs, vec![b"one".to_vec(), b"two".to_vec(), vec![], vec![]]);
+        // an incomplete line is left for the next read, \r and all
+        assert_eq!(buf.peek(), &b"three\r"[..]);
+    }
+
+    #[test]
+    fn line_appends_lf() {
+        let encoded = LineCodec::new().encode(bytes(b"hi")).unwrap();
+        assert_eq!(&encoded[..], &b"hi\n"[..]);
+    }
+
+    #[test]
+    fn length_prefix_layouts() {
+        let cases = [(Width::U16, Endian::Big, vec![0, 3]),
+                     (Width::U16, Endian::Little, vec![3, 0]),
+                     (Width::U32, Endian::Big, vec![0, 0, 0, 3]),
+                     (Width::U32, Endian::Little, vec![3, 0, 0, 0])];
+        for &(width, endian, ref header) in cases.iter() {
+            let mut codec = LengthPrefixedCodec::new(width, endian);
+            let encoded = codec.encode(bytes(b"abc")).unwrap();
+            let mut expected = header.clone();
+            expected.extend_from_slice(b"abc");
+            assert_eq!(&encoded[..], &expected[..], "{:?} {:?}", width, endian);
+
+            let mut buf = filled(&expected);
+            assert_eq!(decode_all(&mut codec, &mut buf), vec![b"abc".to_vec()]);
+            assert!(buf.is_empty());
+        }
+    }
+
+    #[test]
+    fn length_prefix_waits_for_the_whole_message() {
+        let mut codec = LengthPrefixedCodec::new(Width::U16, Endian::Big);
+        let mut buf = filled(&[0]);
+        assert!(codec.decode(&mut buf).unwrap().is_none());
+        let mut buf = filled(&[1, 0, b'x']);
+        assert!(codec.decode(&mut buf).unwrap().is_none());
+        assert_eq!(buf.len(), 3);
+    }
+
+    #[test]
+    fn length_prefix_too_long() {
+        let mut codec = LengthPrefixedCodec::new(Width::U16, Endian::Big);
+        assert!(codec.encode(bytes(&vec![0u8; 0x1_0000])).is_err());
+        let encoded = codec.encode(bytes(&vec![0u8; 0xffff])).unwrap();
+        assert_eq!(&encoded[.. 2], &[0xff, 0xff][..]);
+    }
+
+    #[test]
+    fn fixed_size() {
+        let mut codec = FixedSizeCodec::new(3);
+        let mut buf = filled(b"abcdefgh");
+        assert_eq!(decode_all(&mut codec, &mut buf), vec![b"abc".to_vec(), b"def".to_vec()]);
+        assert_eq!(buf.peek(), &b"gh"[..]);
+
+        assert_eq!(&codec.encode(bytes(b"xyz")).unwrap()[..], &b"xyz"[..]);
+        assert!(codec.encode(bytes(b"xy")).is_err());
+        assert!(codec.encode(bytes(b"wxyz")).is_err());
+    }
+}
@@ -1,21 +1,30 @@
 
-use mio::{EventSet, Evented};
+use std::any::Any;
+
+use mio::{EventSet, Evented, PollOpt};
 use tendril::{Tendril, Atomic};
 use tendril::fmt::Bytes;
 
 use reactor_ctrl::ReactorCtrl;
 
 ///The event types that will be handled by \Context::on_event
+///
+///When the poller reports several kinds of readiness for a socket in the same
+///tick, they are delivered in order: `Readable`, then `Writable`, then `Disconnect`.
 pub enum EventType {
     ///Socket has data waiting to be read
     Readable,
     ///Socket has space available in its buffer for writing
     Writable,
-    ///Remote end of the socket has disconnected
+    ///Remote end of the socket has disconnected, or the poller reported an error on it
     Disconnect,
     ///Notify queue has received a message addressed to this socket
     Notify(Tendril<Bytes, Atomic>),
-    ///A timeout designated for this socket (via timeout_conn) has fired
+    ///A value sent to this socket with Notifier::send_msg, `downcast` it to the type
+    ///that was sent
+    Message(Box<Any + Send>),
+    ///A timeout designated for this socket (via timeout_conn or interval_conn) has fired,
+    ///carrying the tag it was created with
     Timeout(usize)
 }
 
@@ -31,6 +40,101 @@ pub trait Context {
     ///returns the socket so that it can be registered with the event-loop
     fn get_evented(&self) -> &Evented; //&Self::Socket;
 
-    ///returns the current event interest for the loop to register with the poller
+    ///returns the event interest for the loop to register with the poller. This is
+    ///asked for when the Context is registered and after every event it handles, and
+    ///the Context is only reregistered when the answer changes. ReactorCtrl::set_interest
+    ///changes it without waiting for an event, and takes precedence over a change made
+    ///here during the same event
     fn get_interest(&self) -> EventSet;
+
+    ///returns how the Context is registered with the poller: edge, level or oneshot.
+    ///Edge triggered Contexts must read and write until they would block, or they
+    ///will not hear about the rest. Oneshot registrations are re-armed after each
+    ///dispatch. This should not change once the Context is registered.
+    fn get_poll_opt(&self) -> PollOpt {
+        PollOpt::edge()
+    }
+}
+
+/// A Context made from an Evented and a closure, for one-off sockets such as stdin or
+/// a UDP receiver which do not merit a struct of their own. The closure is handed the
+/// Evented, so it can read and write it, and the interest, which it may change, the
+/// change being passed on to `ReactorCtrl::set_interest` when it returns.
+///
+///```ignore
+/// let ctx = FnContext::new(sock, EventSet::readable(), |sock, _interest, _ctrl, evt| {
+///     if let EventType::Readable = evt {
+///         let mut buf = [0u8; 1500];
+///         if let Ok(Some((n, addr))) = sock.recv_from(&mut buf) {
+///             println!("{} bytes from {}", n, addr);
+///         }
+///     }
+/// });
+/// r.register(ctx).unwrap();
+///```
+pub struct FnContext<E, F>
+    where E : Evented,
+          F : FnMut(&mut E, &mut EventSet, &mut ReactorCtrl, EventType)
+{
+    evented: E,
+    interest: EventSet,
+    poll_opt: PollOpt,
+    handler: F
+}
+
+impl<E, F> FnContext<E, F>
+    where E : Evented,
+          F : FnMut(&mut E, &mut EventSet, &mut ReactorCtrl, EventType)
+{
+    pub fn new(evented: E, interest: EventSet, handler: F) -> FnContext<E, F> {
+        FnContext {
+            evented: evented,
+            interest: interest,
+            poll_opt: PollOpt::edge(),
+            handler: handler
+        }
+    }
+
+    /// Register with the given poll mode rather than edge triggered
+    pub fn poll_opt(mut self, opts: PollOpt) -> FnContext<E, F> {
+        self.poll_opt = opts;
+        self
+    }
+
+    pub fn get_ref(&self) -> &E {
+        &self.evented
+    }
+
+    pub fn get_mut(&mut self) -> &mut E {
+        &mut self.evented
+    }
+}
+
+impl<E, F> Context for FnContext<E, F>
+    where E : Evented,
+          F : FnMut(&mut E, &mut EventSet, &mut ReactorCtrl, EventType)
+{
+    fn on_event(&mut self, ctrl: &mut ReactorCtrl, evt: EventType) {
+        let before = self.interest;
+        (self.handler)(&mut self.evented, &mut self.interest, ctrl, evt);
+        if self.interest != before {
+            if let Some(token) = ctrl.current() {
+                if let Err(e) = ctrl.set_interest(token, self.interest) {
+                    debug!("Failed to change interest for {:?}: {}", token, e);
+                }
+            }
+        }
+    }
+
+    fn get_evented(&self) -> &Evented {
+        &self.evented as &Evented
+    }
+
+    fn get_interest(&self) -> EventSet {
+        self.interest
+    }
+
+    fn get_poll_opt(&self) -> PollOpt {
+        self.poll_opt
+    }
 }
@@ -34,7 +34,8 @@
 //!               EventSet,
 //!               Context,
 //!               EventType,
-//!               Token};
+//!               Token,
+//!               SYSTEM_RESOLVER};
 //!
 //! use reactor::tcp::{TcpStream};
 //!
@@ -94,7 +95,7 @@
 //!                    copy_memory("PING!".as_bytes(), &mut buf);
 //!                    self.sock.write(&buf).unwrap();
 //!                    self.count += 1;
-//!                    ctrl.timeout_conn(1000, self.token).unwrap();
+//!                    ctrl.timeout_conn(1000, self.token, 0).unwrap();
 //!                }
 //!                else {
 //!                    ctrl.shutdown();
@@ -135,7 +136,7 @@
 //!                server = Some(tok);
 //!
 //!                //We've received a connection. Initiate PINGPONG protocol in 1 second
-//!                ctrl.timeout_conn(1000, tok).unwrap();
+//!                ctrl.timeout_conn(1000, tok, 0).unwrap();
 //!
 //!                Some(Box::new(ServConn {
 //!                                interest: EventSet::readable(),
@@ -152,7 +153,7 @@
 //!    //Like listen, connect requires that we specify how to create an instance of
 //!    //Context when we successfully complete our connection. In this case we create
 //!    //an instance of ClientConn
-//!    r.connect("localhost", 10000, Box::new(|res, _ctrl| {
+//!    r.connect("localhost", 10000, SYSTEM_RESOLVER, Box::new(|res, _ctrl| {
 //!        match res {
 //!            ConnResult::Connected(sock, tok, addr) => {
 //!                println!("Completing connection to {}", addr);
@@ -175,31 +176,78 @@
 //!```
 //!
 //!
-#![feature(lookup_host)]
-
 #[macro_use]
 extern crate log;
 
 extern crate mio;
 extern crate tendril;
+extern crate time;
+#[cfg(feature = "serde")]
+extern crate serde;
+#[cfg(feature = "serde_json")]
+extern crate serde_json;
+#[cfg(feature = "bincode")]
+extern crate bincode;
+#[cfg(feature = "rmp-serde")]
+extern crate rmp_serde;
 
+mod codec;
 mod context;
+mod pool;
 mod reactor;
 mod reactor_ctrl;
 mod reactor_handler;
+mod resolver;
+#[cfg(any(feature = "json-codec", feature = "bincode-codec", feature = "msgpack-codec"))]
+mod serde_codec;
 pub mod utils;
 
-pub use mio::{EventSet, Evented, Token};
+pub use mio::{EventSet, Evented, PollOpt, Token};
 pub use mio::tcp;
 pub use mio::udp;
 
 pub use reactor::Reactor;
-pub use context::{Context, EventType};
+pub use context::{Context, EventType, FnContext};
+pub use codec::{Codec,
+                FramedHandler,
+                Framed,
+                FramedContext,
+                LineCodec,
+                LengthPrefixedCodec,
+                FixedSizeCodec,
+                Width,
+                Endian};
+#[cfg(feature = "json-codec")]
+pub use serde_codec::JsonCodec;
+#[cfg(feature = "bincode-codec")]
+pub use serde_codec::BincodeCodec;
+#[cfg(feature = "msgpack-codec")]
+pub use serde_codec::MsgPackCodec;
+pub use pool::{ConnPool, PoolConfig, ContextFactory, CheckoutHandler, HealthCheck};
+
+pub use resolver::{Resolver,
+                   Resolution,
+                   ResolverId,
+                   SystemResolver,
+                   StaticResolver,
+                   SYSTEM_RESOLVER};
 
 pub use reactor_ctrl::{ ReactorCtrl,
+                        ReactorConfig,
+                        Notifier,
+                        RemoteHandle,
+                        TaggedBuf,
                         ConnHandler,
                         ConnResult,
                         TimeoutHandler,
+                        ErrorHandler,
+                        OverloadHandler,
                         ListenRec,
-                        TimerRec};
+                        TimerRec,
+                        TimerTarget,
+                        TimerHandle,
+                        Schedule,
+                        CatchUp,
+                        Backoff,
+                        ReactorStats};
 
@@ -0,0 +1,435 @@
+use std::io::{Error, ErrorKind, Result};
+use std::collections::{HashSet, VecDeque};
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use mio::tcp::TcpStream;
+use mio::{Token, Evented, EventSet, PollOpt};
+
+use context::{Context, EventType};
+use resolver::ResolverId;
+use reactor_ctrl::{ReactorCtrl,
+                   ConnResult,
+                   TimerHandle,
+                   Schedule,
+                   now_ms};
+
+/// Sent to every idle pooled Context as `EventType::Message` each
+/// `health_check_interval`, so that it cannot be mistaken for anything the Context
+/// sends itself. A Context which finds its connection is no longer usable
+/// should close itself, and the pool will replace it.
+#[derive(Clone, Copy, Debug)]
+pub struct HealthCheck;
+
+/// min: connections the pool opens up front and keeps open, even when idle
+/// max: the most connections the pool will have open or opening at once
+/// max_waiters: checkouts which may queue for a connection once max is reached,
+/// beyond which `checkout` fails with `WouldBlock`
+/// idle_timeout: milliseconds after which an idle connection beyond min is closed
+/// health_check_interval: milliseconds between health checks of idle connections,
+/// which is also how often idle connections are evicted and min is restored
+/// connect_timeout: milliseconds to allow for each new connection
+#[derive(Clone, Copy, Debug)]
+pub struct PoolConfig {
+    pub min: usize,
+    pub max: usize,
+    pub max_waiters: usize,
+    pub idle_timeout: u64,
+    pub health_check_interval: u64,
+    pub connect_timeout: u64
+}
+
+impl PoolConfig {
+    pub fn new(min: usize, max: usize) -> PoolConfig {
+        PoolConfig {
+            min: min,
+            max: max,
+            max_waiters: 1024,
+            idle_timeout: 60000,
+            health_check_interval: 10000,
+            connect_timeout: 5000
+        }
+    }
+}
+
+/// Builds the Context for every new connection made by the pool
+pub type ContextFactory = FnMut(TcpStream, Token, &mut ReactorCtrl) -> Option<Box<Context>>;
+
+/// Receives the token of a checked out connection, or the reason there is none
+pub type CheckoutHandler = FnMut(Result<Token>, &mut ReactorCtrl);
+
+struct PoolInner {
+    host: String,
+    port: u16,
+    resolver: ResolverId,
+    config: PoolConfig,
+    factory: Option<Box<ContextFactory>>,
+    /// checked in connections with the time they were checked in, most recent at the back
+    idle: VecDeque<(Token, u64)>,
+    busy: HashSet<Token>,
+    /// connections whose Context has been built but not yet registered
+    registering: HashSet<Token>,
+    connecting: usize,
+    waiters: VecDeque<Box<CheckoutHandler>>,
+    maintenance: Option<TimerHandle>,
+    closed: bool
+}
+
+impl PoolInner {
+    fn total(&self) -> usize {
+        self.idle.len() + self.busy.len() + self.registering.len() + self.connecting
+    }
+
+    /// Stop tracking a connection which has gone away
+    fn forget(&mut self, token: Token) {
+        self.busy.remove(&token);
+        self.registering.remove(&token);
+        self.idle.retain(|&(t, _)| t != token);
+    }
+}
+
+/// A pool of outbound connections to one host, such as a database, sharing the
+/// event loop with everything else. Connections are made with `ReactorCtrl::connect`,
+/// each gets its own Context from the factory, and are lent out by token: `checkout`
+/// hands a token to its handler, which may then `notify` the Context, and `checkin`
+/// returns it to the pool.
+///
+/// A connection which is sent `Disconnect` is closed and replaced right away, as is
+/// one whose Context closes itself. One closed with `ReactorCtrl::close` is replaced
+/// at the next maintenance tick. The pool is a cheap handle, clones share the same
+/// pool, and it must only be used on the event loop's thread.
+#[derive(Clone)]
+pub struct ConnPool {
+    inner: Rc<RefCell<PoolInner>>
+}
+
+impl ConnPool {
+
+    /// Create a pool of connections to host:port, opening min of them right away
+    /// and starting the timer which maintains the pool
+    pub fn new(ctrl: &mut ReactorCtrl,
+               host: &str,
+               port: u16,
+               resolver: ResolverId,
+               config: PoolConfig,
+               factory: Box<ContextFactory>) -> Result<ConnPool>
+    {
+        if config.max == 0 || config.min > config.max {
+            return Err(Error::new(ErrorKind::InvalidInput, "Pool sizes must satisfy min <= max and max > 0"));
+        }
+        let pool = ConnPool {
+            inner: Rc::new(RefCell::new(PoolInner {
+                host: host.to_owned(),
+                port: port,
+                resolver: resolver,
+                config: config,
+                factory: Some(factory),
+                idle: VecDeque::new(),
+                busy: HashSet::new(),
+                registering: HashSet::new(),
+                connecting: 0,
+                waiters: VecDeque::new(),
+                maintenance: None,
+                closed: false
+            }))
+        };
+
+        let p = pool.clone();
+        let timer = try!(ctrl.interval(config.health_check_interval, Schedule::FixedDelay,
+                                       Box::new(move |_, ctrl| p.maintain(ctrl))));
+        pool.inner.borrow_mut().maintenance = Some(timer);
+        pool.fill(ctrl);
+        Ok(pool)
+    }
+
+    /// Borrow a connection. The handler is called with its token as soon as one
+    /// is idle, which may be before this returns. If none is and the pool is at max,
+    /// the request waits in line for a checkin, unless max_waiters are already waiting.
+    pub fn checkout(&self, ctrl: &mut ReactorCtrl, mut handler: Box<CheckoutHandler>)
+    {
+        let now = {
+            let mut p = self.inner.borrow_mut();
+            if p.closed {
+                Some(Err(Error::new(ErrorKind::NotConnected, "Pool is closed")))
+            }
+            else if let Some((tok, _)) = p.idle.pop_back() {
+                p.busy.insert(tok);
+                Some(Ok(tok))
+            }
+            else if p.waiters.len() >= p.config.max_waiters {
+                Some(Err(Error::new(ErrorKind::WouldBlock, "Too many checkouts waiting")))
+            }
+            else {
+                None
+            }
+        };
+        match now {
+            Some(res) => handler(res, ctrl),
+            None => {
+                self.inner.borrow_mut().waiters.push_back(handler);
+                self.fill(ctrl);
+            }
+        }
+    }
+
+    /// Return a connection to the pool, handing it straight to the next waiter if any
+    pub fn checkin(&self, ctrl: &mut ReactorCtrl, token: Token)
+    {
+        let close = {
+            let mut p = self.inner.borrow_mut();
+            if !p.busy.remove(&token) {
+                debug!("Checkin of {:?} which is not checked out of the pool", token);
+                return;
+            }
+            if p.closed {
+                true
+            }
+            else {
+                p.idle.push_back((token, now_ms()));
+                false
+            }
+        };
+        if close {
+            ctrl.close(token).ok();
+        }
+        else {
+            self.dispatch(ctrl);
+        }
+    }
+
+    /// Connections in the pool as (idle, checked out, connecting)
+    pub fn size(&self) -> (usize, usize, usize) {
+        let p = self.inner.borrow();
+        (p.idle.len(), p.busy.len(), p.connecting + p.registering.len())
+    }
+
+    /// Close the idle connections, fail the waiters and stop maintaining the pool.
+    /// Checked out connections are closed as they are checked in.
+    pub fn close(&self, ctrl: &mut ReactorCtrl)
+    {
+        let (idle, waiters, timer) = {
+            let mut p = self.inner.borrow_mut();
+            p.closed = true;
+            (p.idle.drain(..).map(|(t, _)| t).collect::<Vec<Token>>(),
+             p.waiters.drain(..).collect::<Vec<Box<CheckoutHandler>>>(),
+             p.maintenance.take())
+        };
+        if let Some(t) = timer {
+            ctrl.cancel_timeout(t);
+        }
+        for tok in idle {
+            ctrl.close(tok).ok();
+        }
+        for mut w in waiters {
+            w(Err(Error::new(ErrorKind::NotConnected, "Pool is closed")), ctrl);
+        }
+    }
+
+    /// Hand idle connections to waiters, in the order they started waiting
+    fn dispatch(&self, ctrl: &mut ReactorCtrl)
+    {
+        loop {
+            let next = {
+                let mut p = self.inner.borrow_mut();
+                if p.idle.is_empty() || p.waiters.is_empty() {
+                    None
+                }
+                else {
+                    let (tok, _) = p.idle.pop_back().unwrap();
+                    p.busy.insert(tok);
+                    Some((tok, p.waiters.pop_front().unwrap()))
+                }
+            };
+            match next {
+                Some((tok, mut w)) => w(Ok(tok), ctrl),
+                None => return
+            }
+        }
+    }
+
+    /// Open connections until there are min, or enough for everyone waiting, up to max.
+    /// Stops at the first connection which fails to start, the maintenance timer
+    /// tries again, as retrying at once would most likely fail the same way forever
+    fn fill(&self, ctrl: &mut ReactorCtrl)
+    {
+        loop {
+            let more = {
+                let p = self.inner.borrow();
+                !p.closed && p.total() < p.config.max &&
+                    (p.total() < p.config.min || p.connecting < p.waiters.len())
+            };
+            if !more || !self.open(ctrl) {
+                return;
+            }
+        }
+    }
+
+    /// Start one new connection for the pool, returning whether it could be started
+    fn open(&self, ctrl: &mut ReactorCtrl) -> bool
+    {
+        let (host, port, resolver, timeout) = {
+            let mut p = self.inner.borrow_mut();
+            p.connecting += 1;
+            (p.host.clone(), p.port, p.resolver, p.config.connect_timeout)
+        };
+        let pool = self.clone();
+        let res = ctrl.connect_timeout(&host, port, resolver, timeout,
+                                       Box::new(move |res, ctrl| pool.connected(res, ctrl)));
+        match res {
+            Ok(_) => true,
+            Err(e) => {
+                self.connect_failed(e, ctrl);
+                false
+            }
+        }
+    }
+
+    /// The ConnHandler for every connection made by the pool
+    fn connected(&self, res: ConnResult, ctrl: &mut ReactorCtrl) -> Option<Box<Context>>
+    {
+        let (sock, tok) = match res {
+            ConnResult::Connected(sock, tok, _) => (sock, tok),
+            ConnResult::Failed(e) => {
+                self.connect_failed(e, ctrl);
+                return None;
+            }
+        };
+
+        // the factory is taken out while it runs, so that it may use the pool
+        let factory = {
+            let mut p = self.inner.borrow_mut();
+            p.connecting -= 1;
+            if p.closed { None } else { p.factory.take() }
+        };
+        let mut factory = match factory {
+            Some(f) => f,
+            None => return None
+        };
+        let ctx = factory(sock, tok, ctrl);
+        self.inner.borrow_mut().factory = Some(factory);
+
+        let ctx = match ctx {
+            Some(ctx) => ctx,
+            None => {
+                self.fill(ctrl);
+                return None;
+            }
+        };
+        // the Context is registered once this returns, only then may it be lent out
+        self.inner.borrow_mut().registering.insert(tok);
+        let pool = self.clone();
+        if let Err(e) = ctrl.timeout(0, Box::new(move |_, ctrl| pool.registered(tok, ctrl))) {
+            debug!("Failed to defer making {:?} idle, it waits for a checkin: {}", tok, e);
+            let mut p = self.inner.borrow_mut();
+            p.registering.remove(&tok);
+            p.idle.push_back((tok, now_ms()));
+        }
+        Some(Box::new(Pooled { ctx: ctx, token: tok, pool: self.clone() }))
+    }
+
+    /// A new connection's Context has been registered, unless it has since gone away
+    fn registered(&self, token: Token, ctrl: &mut ReactorCtrl)
+    {
+        let close = {
+            let mut p = self.inner.borrow_mut();
+            if !p.registering.remove(&token) {
+                return;
+            }
+            if !p.closed {
+                p.idle.push_back((token, now_ms()));
+            }
+            p.closed
+        };
+        if close {
+            ctrl.close(token).ok();
+        }
+        else {
+            self.dispatch(ctrl);
+        }
+    }
+
+    /// A connection could not be made, if there are now more waiters than
+    /// connections on the way, the longest waiting is told why
+    fn connect_failed(&self, err: Error, ctrl: &mut ReactorCtrl)
+    {
+        warn!("Pool connection failed: {}", err);
+        let waiter = {
+            let mut p = self.inner.borrow_mut();
+            p.connecting -= 1;
+            if p.waiters.len() > p.connecting { p.waiters.pop_front() } else { None }
+        };
+        if let Some(mut w) = waiter {
+            w(Err(err), ctrl);
+        }
+    }
+
+    /// Run by the maintenance timer: close connections which have been idle too long,
+    /// health check the rest, and top the pool back up to min
+    fn maintain(&self, ctrl: &mut ReactorCtrl)
+    {
+        let (evict, check) = {
+            let mut p = self.inner.borrow_mut();
+            let now = now_ms();
+            let mut evict = Vec::new();
+            // the oldest idle connections are at the front
+            while p.total() > p.config.min {
+                match p.idle.front() {
+                    Some(&(tok, since)) if now - since >= p.config.idle_timeout => evict.push(tok),
+                    _ => break
+                }
+                p.idle.pop_front();
+            }
+            (evict, p.idle.iter().map(|&(t, _)| t).collect::<Vec<Token>>())
+        };
+        for tok in evict {
+            debug!("Closing idle pool connection {:?}", tok);
+            ctrl.close(tok).ok();
+        }
+        let notifier = ctrl.channel();
+        for tok in check {
+            if let Err(e) = notifier.send_msg(tok, HealthCheck) {
+                debug!("Failed to send health check to {:?}: {:?}", tok, e);
+            }
+        }
+        self.fill(ctrl);
+    }
+}
+
+/// Wraps the factory's Context for a pooled connection, so that the pool learns
+/// when the connection goes away
+struct Pooled {
+    ctx: Box<Context>,
+    token: Token,
+    pool: ConnPool
+}
+
+impl Context for Pooled {
+
+    fn on_event(&mut self, ctrl: &mut ReactorCtrl, evt: EventType) {
+        let lost = match evt { EventType::Disconnect => true, _ => false };
+        self.ctx.on_event(ctrl, evt);
+        if lost || ctrl.closing() {
+            ctrl.close_current().ok();
+            self.pool.inner.borrow_mut().forget(self.token);
+            self.pool.fill(ctrl);
+        }
+    }
+
+    fn get_evented(&self) -> &Evented {
+        self.ctx.get_evented()
+    }
+
+    fn get_interest(&self) -> EventSet {
+        self.ctx.get_interest()
+    }
+
+    fn get_poll_opt(&self) -> PollOpt {
+        self.ctx.get_poll_opt()
+    }
+}
+
+impl Drop for Pooled {
+    fn drop(&mut self) {
+        self.pool.inner.borrow_mut().forget(self.token);
+    }
+}
@@ -1,14 +1,22 @@
 use std::io::{Result};
-use std::net::ToSocketAddrs;
+use std::net::{SocketAddr, ToSocketAddrs};
 
-use mio::{Sender, Evented, EventLoop, EventLoopConfig, Token, TimerResult, Timeout};
+use mio::{Evented, EventLoop, EventLoopConfig, EventSet, Token};
 use reactor_handler::{ReactorHandler};
+use resolver::{Resolver, ResolverId};
 use context::{Context};
 use reactor_ctrl::{ReactorCtrl,
+                   ReactorStats,
+                   Backoff,
                    ReactorConfig,
                    ReactorState,
-                   TaggedBuf,
+                   Notifier,
+                   RemoteHandle,
                    ConnHandler,
+                   TimerHandle,
+                   Schedule,
+                   ErrorHandler,
+                   OverloadHandler,
                    TimeoutHandler};
 
 pub struct Reactor<'a>
@@ -23,20 +31,15 @@ impl<'a> Reactor<'a>
 
     /// Construct a new Reactor with (hopefully) intelligent defaults
     pub fn new() -> Reactor<'a> {
-        let config = ReactorConfig {
-            out_queue_size: 524288,
-            max_connections: 10240,
-            timers_per_connection: 1
-        };
-
-        Self::configured(config)
+        Self::configured(ReactorConfig::default())
     }
 
     /// Construct a new engine with defaults specified by the user
     pub fn configured(cfg: ReactorConfig) -> Reactor<'a> {
+        // mio's timer is sized once, for as many timers as the timeouts slab may grow to
         let eloop = EventLoop::configured(
                     Self::event_loop_config(
-                        cfg.out_queue_size, (cfg.max_connections * cfg.timers_per_connection))).unwrap();
+                        cfg.out_queue_size, cfg.timer_slots(cfg.max_connections))).unwrap();
 
         let state = ReactorState::new(cfg);
 
@@ -61,12 +64,46 @@ impl<'a> Reactor<'a>
     /// return immediately.  It requires a handler, to which it will supply a `ConnResult` which
     /// will indicate success or failure. On success, it will supply a socket, a token, and a
     /// remote IP addr. It then expects an Option<Box<`Context`>> so that it can manage its events
+    /// Host names are looked up with the given resolver without blocking the event loop,
+    /// use `SYSTEM_RESOLVER` for the operating system's own. Every address the host resolves
+    /// to is tried, see `ReactorCtrl::connect`.
     pub fn connect<'b>(&mut self,
                    hostname: &'b str,
-                   port: usize,
+                   port: u16,
+                   resolver: ResolverId,
+                   handler: Box<ConnHandler<'a>>) -> Result<Token> {
+        ReactorCtrl::new(self.state.as_mut().unwrap(), &mut self.event_loop)
+            .connect(hostname, port, resolver, handler)
+    }
+
+    /// As `connect`, but the connection is abandoned with an error of kind `TimedOut`
+    /// if it has not been established within timeout milliseconds
+    pub fn connect_timeout<'b>(&mut self,
+                   hostname: &'b str,
+                   port: u16,
+                   resolver: ResolverId,
+                   timeout: u64,
+                   handler: Box<ConnHandler<'a>>) -> Result<Token> {
+        ReactorCtrl::new(self.state.as_mut().unwrap(), &mut self.event_loop)
+            .connect_timeout(hostname, port, resolver, timeout, handler)
+    }
+
+    /// As `connect`, but the connection is re-established with backoff whenever it fails
+    /// or is lost, under the same token, see `ReactorCtrl::reconnect`
+    pub fn reconnect<'b>(&mut self,
+                   hostname: &'b str,
+                   port: u16,
+                   resolver: ResolverId,
+                   backoff: Backoff,
                    handler: Box<ConnHandler<'a>>) -> Result<Token> {
         ReactorCtrl::new(self.state.as_mut().unwrap(), &mut self.event_loop)
-            .connect(hostname, port, handler)
+            .reconnect(hostname, port, resolver, backoff, handler)
+    }
+
+    /// Hand a Resolver to the reactor, the returned id can then be passed to `connect`
+    pub fn add_resolver(&mut self, resolver: Box<Resolver>) -> ResolverId {
+        ReactorCtrl::new(self.state.as_mut().unwrap(), &mut self.event_loop)
+            .add_resolver(resolver)
     }
 
     /// Listen on the supplied IP address:port for incoming TCP connections.  This returns
@@ -79,15 +116,58 @@ impl<'a> Reactor<'a>
             .listen(addr, handler)
     }
 
+    /// The address the listener for token is bound to. Listen on port 0 to have the
+    /// OS pick a free port, and ask this which one it picked.
+    ///
+    ///```
+    /// extern crate reactor;
+    ///
+    /// use reactor::Reactor;
+    ///
+    /// fn main() {
+    ///     let mut r = Reactor::new();
+    ///     let listener = r.listen(("127.0.0.1", 0), Box::new(|_, _| None)).unwrap();
+    ///     assert!(r.local_addr(listener).unwrap().port() != 0);
+    /// }
+    ///```
+    pub fn local_addr(&mut self, token: Token) -> Result<SocketAddr> {
+        ReactorCtrl::new(self.state.as_mut().unwrap(), &mut self.event_loop)
+            .local_addr(token)
+    }
+
+    /// A handle for running closures on the event loop from other threads,
+    /// see `RemoteHandle`
+    pub fn remote(&self) -> RemoteHandle {
+        RemoteHandle::new(self.event_loop.channel())
+    }
+
+    /// Stop listening on the listener for token, and get back its handler
+    pub fn unlisten(&mut self, token: Token) -> Result<Box<ConnHandler<'a>>> {
+        ReactorCtrl::new(self.state.as_mut().unwrap(), &mut self.event_loop)
+            .unlisten(token)
+    }
+
+    /// Stop accepting connections on a listener until `resume_listener` is called
+    pub fn pause_listener(&mut self, token: Token) -> Result<()> {
+        ReactorCtrl::new(self.state.as_mut().unwrap(), &mut self.event_loop)
+            .pause_listener(token)
+    }
+
+    /// Start accepting connections on a paused listener again
+    pub fn resume_listener(&mut self, token: Token) -> Result<()> {
+        ReactorCtrl::new(self.state.as_mut().unwrap(), &mut self.event_loop)
+            .resume_listener(token)
+    }
+
     /// fetch the event_loop channel for notifying the event_loop of new outbound data
-    pub fn channel(&self) -> Sender<TaggedBuf> {
-        self.event_loop.channel()
+    pub fn channel(&self) -> Notifier {
+        Notifier::new(self.event_loop.channel())
     }
 
     /// Set a timeout to be executed by the event loop after duration milliseconds
     /// The supplied handler, which is a FnMut will be invoked no sooner than the
     /// timeout
-    pub fn timeout(&mut self, duration: u64, handler: Box<TimeoutHandler<'a>>) -> TimerResult<(Timeout, Token)> {
+    pub fn timeout(&mut self, duration: u64, handler: Box<TimeoutHandler<'a>>) -> Result<TimerHandle> {
         ReactorCtrl::new(self.state.as_mut().unwrap(), &mut self.event_loop)
             .timeout(duration, handler)
     }
@@ -96,9 +176,36 @@ impl<'a> Reactor<'a>
     /// ctxtok specifies a Context to which the timer callback will be directed
     /// through the usual event dispatch mechanism for `Context`s
     /// This is useful for handling protocols which have a ping/pong style timeout
-    pub fn timeout_conn(&mut self, duration: u64, ctxtok: Token) -> TimerResult<(Timeout, Token)> {
+    /// tag is handed back to the Context in `EventType::Timeout(tag)`
+    pub fn timeout_conn(&mut self, duration: u64, ctxtok: Token, tag: usize) -> Result<TimerHandle> {
         ReactorCtrl::new(self.state.as_mut().unwrap(), &mut self.event_loop)
-            .timeout_conn(duration, ctxtok)
+            .timeout_conn(duration, ctxtok, tag)
+    }
+
+    /// Invoke handler every period milliseconds until the timer is cancelled
+    pub fn interval(&mut self, period: u64, schedule: Schedule, handler: Box<TimeoutHandler<'a>>) -> Result<TimerHandle> {
+        ReactorCtrl::new(self.state.as_mut().unwrap(), &mut self.event_loop)
+            .interval(period, schedule, handler)
+    }
+
+    /// Deliver a Timeout(tag) event to the Context for ctxtok every period milliseconds
+    /// until the timer is cancelled
+    pub fn interval_conn(&mut self, period: u64, schedule: Schedule, ctxtok: Token, tag: usize) -> Result<TimerHandle> {
+        ReactorCtrl::new(self.state.as_mut().unwrap(), &mut self.event_loop)
+            .interval_conn(period, schedule, ctxtok, tag)
+    }
+
+    /// Cancel a pending timeout, returns false if it had already fired or been cancelled
+    pub fn cancel_timeout(&mut self, handle: TimerHandle) -> bool {
+        ReactorCtrl::new(self.state.as_mut().unwrap(), &mut self.event_loop)
+            .cancel_timeout(handle)
+    }
+
+    /// Move a pending timeout so that it fires after duration milliseconds from now
+    /// returns Ok(false) if it had already fired or been cancelled
+    pub fn reschedule_timeout(&mut self, handle: &TimerHandle, duration: u64) -> Result<bool> {
+        ReactorCtrl::new(self.state.as_mut().unwrap(), &mut self.event_loop)
+            .reschedule_timeout(handle, duration)
     }
 
     /// Trade in an existing context (connected to a resource) and get a Token
@@ -111,6 +218,17 @@ impl<'a> Reactor<'a>
             .register(ctx)
     }
 
+    /// Change the events the Context for token is registered for, see `ReactorCtrl::set_interest`
+    pub fn set_interest(&mut self, token: Token, interest: EventSet) -> Result<()> {
+        ReactorCtrl::new(self.state.as_mut().unwrap(), &mut self.event_loop)
+            .set_interest(token, interest)
+    }
+
+    /// The reactor's counters since it was created
+    pub fn stats(&self) -> ReactorStats {
+        self.state.as_ref().unwrap().stats
+    }
+
     /// Trade in your token for a Context and deregister the Context's socket/evented
     /// from the event_loop
     pub fn deregister(&mut self, token: Token) -> Result<Box<Context>>
@@ -119,6 +237,34 @@ impl<'a> Reactor<'a>
             .deregister(token)
     }
 
+    /// Close the context for a given token, deregistering it from the event_loop
+    /// and dropping it
+    pub fn close(&mut self, token: Token) -> Result<()>
+    {
+        ReactorCtrl::new(self.state.as_mut().unwrap(), &mut self.event_loop)
+            .close(token)
+    }
+
+    /// Set a handler to be told about errors which occur inside the event loop,
+    /// such as a failed accept or a Context which could not be reregistered.
+    /// The token is that of the listener or Context concerned, if there is one.
+    /// These errors are always logged, whether or not a handler is set.
+    pub fn on_error(&mut self, handler: Box<ErrorHandler<'a>>) {
+        self.state.as_mut().unwrap().error_handler = Some(handler);
+    }
+
+    /// The control interface which handlers are given, for setting up anything which
+    /// needs one, such as a `ConnPool`, before the event loop is run
+    pub fn ctrl<'b>(&'b mut self) -> ReactorCtrl<'b, 'a> {
+        ReactorCtrl::new(self.state.as_mut().unwrap(), &mut self.event_loop)
+    }
+
+    /// Set a hook to be called with the listener's token and the peer's address whenever
+    /// an inbound connection is turned away because max_connections has been reached
+    pub fn on_overload(&mut self, handler: Box<OverloadHandler<'a>>) {
+        self.state.as_mut().unwrap().overload_handler = Some(handler);
+    }
+
     /// process all incoming and outgoing events in a loop
     pub fn run(&mut self) {
         self.handler.state = self.state.take();
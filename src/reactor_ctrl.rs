@@ -1,9 +1,13 @@
 use std::net::{SocketAddr,
-               lookup_host,
-               SocketAddrV4,
-               ToSocketAddrs,
-               SocketAddrV6};
+               IpAddr,
+               ToSocketAddrs};
+use std::result;
+use std::cmp;
+use std::mem;
+use std::any::Any;
 use std::io::{Error, ErrorKind, Result};
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::sync::{Arc, Mutex};
 
 use mio::tcp::{TcpStream, TcpListener};
 use mio::util::{Slab};
@@ -14,16 +18,105 @@ use mio::{Token,
           PollOpt,
           Timeout,
           Sender,
-          TimerResult};
+          NotifyError};
 
+use time::precise_time_ns;
 use tendril::{Tendril, Atomic};
 use tendril::fmt::Bytes;
 
 use reactor_handler::ReactorHandler;
 use context::{Context};
+use resolver::{Resolver, ResolverId, Resolution, SystemResolver, LateResolutions};
 
 pub type TaggedBuf = (Token, Tendril<Bytes, Atomic>);
 
+/// Everything which arrives on the event loop's notify channel
+#[doc(hidden)]
+pub enum ReactorMsg {
+    Notify(TaggedBuf),
+    Message(Token, Box<Any + Send>),
+    Run(Box<FnMut(&mut ReactorCtrl) + Send>),
+    Resolved(Token, Result<Vec<SocketAddr>>)
+}
+
+/// The sending half of the event loop channel, returned by `channel()`.
+/// It can be cloned and sent to other threads, and delivers each `TaggedBuf`
+/// to the Context for its token as `EventType::Notify`, or any other value
+/// as `EventType::Message`
+#[derive(Clone)]
+pub struct Notifier {
+    sender: Sender<ReactorMsg>
+}
+
+impl Notifier {
+
+    pub(crate) fn new(sender: Sender<ReactorMsg>) -> Notifier {
+        Notifier { sender: sender }
+    }
+
+    pub fn send(&self, msg: TaggedBuf) -> result::Result<(), NotifyError<TaggedBuf>> {
+        self.sender.send(ReactorMsg::Notify(msg)).map_err(|e| match e {
+            NotifyError::Io(e) => NotifyError::Io(e),
+            NotifyError::Full(ReactorMsg::Notify(m)) => NotifyError::Full(m),
+            NotifyError::Closed(Some(ReactorMsg::Notify(m))) => NotifyError::Closed(Some(m)),
+            _ => NotifyError::Closed(None)
+        })
+    }
+
+    /// Send a value of any type to the Context for token, which receives it boxed in
+    /// `EventType::Message` and can `downcast` it back, so that structured messages
+    /// need not be serialized to bytes. On failure the value is handed back, boxed.
+    pub fn send_msg<M : Any + Send>(&self, token: Token, msg: M) -> result::Result<(), NotifyError<Box<Any + Send>>> {
+        self.sender.send(ReactorMsg::Message(token, Box::new(msg))).map_err(|e| match e {
+            NotifyError::Io(e) => NotifyError::Io(e),
+            NotifyError::Full(ReactorMsg::Message(_, m)) => NotifyError::Full(m),
+            NotifyError::Closed(Some(ReactorMsg::Message(_, m))) => NotifyError::Closed(Some(m)),
+            _ => NotifyError::Closed(None)
+        })
+    }
+}
+
+/// Runs closures on the event loop's thread from any other thread, returned by
+/// `remote()`. A closure is given the same `ReactorCtrl` as every handler, so it
+/// can listen, connect, register, set timeouts or shut the loop down.
+///
+///```ignore
+/// let remote = r.remote();
+/// thread::spawn(move || {
+///     // stop taking new connections while we do maintenance
+///     remote.run(move |ctrl| { ctrl.pause_listener(ltoken).unwrap(); }).ok();
+/// });
+///```
+#[derive(Clone)]
+pub struct RemoteHandle {
+    sender: Sender<ReactorMsg>
+}
+
+impl RemoteHandle {
+
+    pub(crate) fn new(sender: Sender<ReactorMsg>) -> RemoteHandle {
+        RemoteHandle { sender: sender }
+    }
+
+    /// Queue f to be run once on the event loop's thread. It fails if the
+    /// channel is full or the event loop has gone, in which case f is dropped.
+    pub fn run<F>(&self, f: F) -> result::Result<(), NotifyError<()>>
+        where F : FnOnce(&mut ReactorCtrl) + Send + 'static
+    {
+        let mut f = Some(f);
+        let task = move |ctrl: &mut ReactorCtrl| {
+            if let Some(f) = f.take() {
+                f(ctrl);
+            }
+        };
+        self.sender.send(ReactorMsg::Run(Box::new(task))).map_err(|e| match e {
+            NotifyError::Io(e) => NotifyError::Io(e),
+            NotifyError::Full(_) => NotifyError::Full(()),
+            NotifyError::Closed(_) => NotifyError::Closed(None)
+        })
+    }
+}
+
 pub enum ConnResult {
     Connected(TcpStream, Token, SocketAddr),
     Failed(Error)
@@ -31,45 +124,913 @@ pub enum ConnResult {
 
 pub type ConnHandler<'a> = FnMut(ConnResult, &mut ReactorCtrl) -> Option<Box<Context>> + 'a;
 pub type TimeoutHandler<'a> = FnMut(Token, &mut ReactorCtrl) + 'a;
+pub type ErrorHandler<'a> = FnMut(Option<Token>, Error) + 'a;
+pub type OverloadHandler<'a> = FnMut(Token, SocketAddr) + 'a;
 
 pub type ListenRec<'a> = Option<(TcpListener, Box<ConnHandler<'a>>)>;
-pub type TimerRec<'a> = (Option<Token>, Option<Box<TimeoutHandler<'a>>>);
+
+/// Where a timeout is delivered when it fires
+pub enum TimerTarget<'a> {
+    /// Delivered to the Context for this token as `EventType::Timeout`
+    /// carrying the caller supplied tag
+    Conn(Token, usize),
+    /// Delivered to a standalone `TimeoutHandler`
+    Handler(Box<TimeoutHandler<'a>>),
+    /// Fails the pending outbound connection for this token with `TimedOut`
+    Connect(Token),
+    /// Starts the next address attempt for the pending outbound connection for this token
+    Stagger(Token),
+    /// Retries the reconnecting outbound connection for this token once its backoff is over
+    Reconnect(Token),
+    /// Accepts again on the listener for this token, after accepting failed
+    Accept(Token)
+}
+
+/// How a recurring timer picks the deadline of its next tick
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Schedule {
+    /// Ticks are due at whole multiples of the period from the first deadline,
+    /// regardless of how long the handler takes
+    FixedRate(CatchUp),
+    /// The next tick is due one period after the previous tick has been handled
+    FixedDelay
+}
+
+/// What a `Schedule::FixedRate` timer does when the loop has fallen behind
+/// by more than one period
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CatchUp {
+    /// Deliver every missed tick, back to back
+    Burst,
+    /// Deliver a single tick and skip ahead to the next deadline in the future
+    Skip
+}
+
+/// The recurrence of an interval timer
+#[derive(Clone, Copy, Debug)]
+pub struct Interval {
+    pub period: u64,
+    pub schedule: Schedule,
+    /// when the next tick is due, in milliseconds as measured by `now_ms`
+    pub deadline: u64
+}
+
+pub struct TimerRec<'a> {
+    /// taken while the timer is being dispatched
+    pub target: Option<TimerTarget<'a>>,
+    /// the Context this timer counts against, see `ReactorConfig::timers_per_connection`
+    pub owner: Option<Token>,
+    pub timeout: Option<Timeout>,
+    pub interval: Option<Interval>,
+    pub serial: u64
+}
+
+/// A handle to a pending timeout, returned by `timeout`, `timeout_conn`,
+/// `interval` and `interval_conn`.
+/// Use it with `cancel_timeout` or `reschedule_timeout`.
+/// A handle whose timer has already fired or been cancelled is simply ignored.
+#[derive(Debug)]
+pub struct TimerHandle {
+    token: Token,
+    serial: u64
+}
+
+impl TimerHandle {
+    /// The token of the timer's slot, which is passed to a `TimeoutHandler`. Timers set
+    /// for a Context are told apart by the tag carried in `EventType::Timeout` instead
+    pub fn token(&self) -> Token {
+        self.token
+    }
+}
+
+/// How long a reconnecting connection waits before each retry, see `ReactorCtrl::reconnect`.
+/// The nth consecutive failure waits `initial * multiplier^(n-1)` milliseconds, at most `max`,
+/// less a random fraction of up to `jitter` of that so that many clients which lost the same
+/// peer do not all come back at once. After `max_retries` consecutive failures, if set, the
+/// connection is given up on. A successful connection resets the count.
+#[derive(Clone, Copy, Debug)]
+pub struct Backoff {
+    pub initial: u64,
+    pub max: u64,
+    pub multiplier: f64,
+    pub jitter: f64,
+    pub max_retries: Option<u32>
+}
+
+impl Backoff {
+
+    /// Doubling delays from initial to max milliseconds, with up to 20% jitter,
+    /// retrying forever
+    pub fn new(initial: u64, max: u64) -> Backoff {
+        Backoff {
+            initial: initial,
+            max: max,
+            multiplier: 2.0,
+            jitter: 0.2,
+            max_retries: None
+        }
+    }
+
+    /// The delay before retrying after the given number of consecutive failures,
+    /// random is a uniformly distributed value used for the jitter
+    pub fn delay(&self, failures: u32, random: u64) -> u64 {
+        let exp = if failures > 0 { failures as i32 - 1 } else { 0 };
+        let mut base = self.initial as f64 * self.multiplier.powi(exp);
+        if !(base < self.max as f64) {
+            base = self.max as f64;
+        }
+        let frac = (random >> 11) as f64 / (1u64 << 53) as f64;
+        (base * (1.0 - self.jitter * frac)) as u64
+    }
+}
+
+/// What a reconnecting connection needs to make its next attempt
+pub struct Retry {
+    pub host: String,
+    pub port: u16,
+    pub resolver: ResolverId,
+    pub backoff: Backoff,
+    /// consecutive failures since the last successful connection
+    pub failures: u32,
+    /// the end of the current backoff, while the connection is `Waiting`
+    pub timer: Option<TimerHandle>
+}
+
+/// An outbound connection being established, see `ReactorCtrl::connect`.
+/// Every address the host resolved to is tried in turn, a new attempt being
+/// started whenever one fails or the previous one has been outstanding for
+/// `ReactorConfig::connect_attempt_delay`, as in RFC 8305 (happy eyeballs).
+/// The first attempt to succeed wins and the rest are abandoned.
+pub struct Connect<'a> {
+    pub handler: Box<ConnHandler<'a>>,
+    /// addresses still to try, in the order they will be tried
+    pub addrs: VecDeque<SocketAddr>,
+    /// the tokens of the attempts in flight, each of which holds a `ConnRec::Attempt`
+    pub attempts: Vec<Token>,
+    /// abandons the whole connection, see `connect_timeout`
+    pub deadline: Option<TimerHandle>,
+    /// starts the next attempt if those in flight are slow to complete
+    pub stagger: Option<TimerHandle>,
+    /// why the most recent attempt failed, reported if they all do
+    pub error: Option<Error>,
+    /// set for a connection made with `reconnect`, which is retried rather than failed
+    pub retry: Option<Retry>
+}
 
 pub enum ConnRec<'a> {
     Connected(Box<Context>),
-    Pending(TcpStream, Box<ConnHandler<'a>>),
+    /// an outbound connection waiting on its Resolver
+    Resolving(Connect<'a>),
+    /// an outbound connection with attempts in progress
+    Pending(Connect<'a>),
+    /// a reconnecting connection waiting out its backoff before the next retry
+    Waiting(Connect<'a>),
+    /// a single connection attempt to one address, on behalf of the `Pending` connection for the token
+    Attempt(TcpStream, Token),
     None
 }
 
+/// The low bits of a connection Token index its slot, the rest hold the generation
+/// of the slot, which changes every time it is released. A Token kept, or a message
+/// queued, for a connection which has since closed will then not reach whatever
+/// reuses its slot.
+pub const INDEX_BITS: usize = 24;
+const INDEX_MASK: usize = (1 << INDEX_BITS) - 1;
+const GENERATION_MASK: usize = ::std::usize::MAX >> INDEX_BITS;
+
+/// The conns slab, handing out and checking generational Tokens. Every lookup with
+/// a Token whose generation is not the slot's current one finds nothing.
+pub struct ConnSlab<'a> {
+    slab: Slab<ConnRec<'a>>,
+    generations: Vec<usize>,
+    offset: usize
+}
+
+impl<'a> ConnSlab<'a> {
+
+    pub fn new_starting_at(offset: Token, capacity: usize) -> ConnSlab<'a> {
+        ConnSlab {
+            slab: Slab::new_starting_at(offset, capacity),
+            generations: Vec::new(),
+            offset: offset.0
+        }
+    }
+
+    /// The slab's own token for token, if its generation is current
+    fn lookup(&self, token: Token) -> Option<Token> {
+        let idx = token.0 & INDEX_MASK;
+        if idx < self.offset {
+            return None;
+        }
+        match self.generations.get(idx - self.offset) {
+            Some(&gen) if gen == token.0 >> INDEX_BITS => Some(Token(idx)),
+            _ => None
+        }
+    }
+
+    pub fn insert(&mut self, rec: ConnRec<'a>) -> result::Result<Token, ConnRec<'a>> {
+        let idx = try!(self.slab.insert(rec));
+        let slot = idx.0 - self.offset;
+        if slot >= self.generations.len() {
+            self.generations.resize(slot + 1, 0);
+        }
+        Ok(Token(idx.0 | self.generations[slot] << INDEX_BITS))
+    }
+
+    pub fn remove(&mut self, token: Token) -> Option<ConnRec<'a>> {
+        let idx = match self.lookup(token) {
+            Some(idx) => idx,
+            None => return None
+        };
+        let rec = self.slab.remove(idx);
+        if rec.is_some() {
+            let slot = idx.0 - self.offset;
+            self.generations[slot] = self.generations[slot].wrapping_add(1) & GENERATION_MASK;
+        }
+        rec
+    }
+
+    /// Put rec in token's slot, returning what was there. Unlike `Slab::replace`,
+    /// which takes replacing a filled slot for a removal, the slot stays filled
+    pub fn replace(&mut self, token: Token, rec: ConnRec<'a>) -> Option<ConnRec<'a>> {
+        self.get_mut(token).map(|r| mem::replace(r, rec))
+    }
+
+    pub fn get(&self, token: Token) -> Option<&ConnRec<'a>> {
+        self.lookup(token).and_then(|idx| self.slab.get(idx))
+    }
+
+    pub fn get_mut(&mut self, token: Token) -> Option<&mut ConnRec<'a>> {
+        match self.lookup(token) {
+            Some(idx) => self.slab.get_mut(idx),
+            None => None
+        }
+    }
+
+    pub fn contains(&self, token: Token) -> bool {
+        self.lookup(token).map_or(false, |idx| self.slab.contains(idx))
+    }
+
+    /// Make room for one more connection, growing up to cap, see `make_room`
+    fn make_room(&mut self, cap: usize) -> bool {
+        make_room(&mut self.slab, cmp::min(cap, INDEX_MASK - self.offset))
+    }
+}
+
 /// Configuration for the Reactor
 /// queue_size: All queues, both inbound and outbound
+/// timers_per_connection: the number of `timeout_conn`/`interval_conn` timers
+/// a single Context may have pending at once, 1 by default. Raise it for Contexts
+/// which keep several, such as a read deadline and a ping, each costs a timer slot
+/// for every connection. See `timer_slots` for how many timers there are in all
+/// accepts_per_tick: the most connections a listener will accept in one tick
+/// before giving other sockets a turn, 0 for no limit
+/// connect_attempt_delay: milliseconds to wait on one address of a multi-address
+/// host before also trying the next
+/// initial_connections: connection slots, and timer slots to go with them, allocated
+/// up front. They grow on demand up to the hard cap of max_connections. Besides
+/// connected Contexts, every outbound connection which is resolving, connecting or
+/// waiting to reconnect takes a slot, as does each connection attempt it has in flight
+/// overload_response: written to inbound connections which are turned away because
+/// max_connections has been reached, before they are closed
 pub struct ReactorConfig {
     pub out_queue_size: usize,
+    pub initial_connections: usize,
     pub max_connections: usize,
-    pub timers_per_connection: usize
+    pub timers_per_connection: usize,
+    pub accepts_per_tick: usize,
+    pub connect_attempt_delay: u64,
+    pub overload_response: Option<Vec<u8>>
+}
+
+impl ReactorConfig {
+
+    /// The timer slots which go with conns connection slots: timers_per_connection
+    /// for each, and one more each for the reactor's own connect deadlines, address
+    /// staggering, reconnect backoff and accept retries. Standalone `timeout` and
+    /// `interval` timers come out of the same slots, so a Context may find its
+    /// `timeout_conn` refused when it is under timers_per_connection if the timers
+    /// are full. With max_connections this is the hard cap on timers.
+    pub fn timer_slots(&self, conns: usize) -> usize {
+        conns * (self.timers_per_connection + 1)
+    }
+}
+
+impl Default for ReactorConfig {
+    fn default() -> ReactorConfig {
+        ReactorConfig {
+            out_queue_size: 524288,
+            initial_connections: 1024,
+            max_connections: 10240,
+            timers_per_connection: 1,
+            accepts_per_tick: 128,
+            connect_attempt_delay: 250,
+            overload_response: None
+        }
+    }
+}
+
+/// Counters kept by the reactor, see `ReactorCtrl::stats`
+#[derive(Clone, Copy, Debug, Default)]
+pub struct ReactorStats {
+    /// Contexts reregistered with the poller, because their interest changed or to
+    /// re-arm a oneshot registration
+    pub reregisters: u64,
+    /// dispatches after which a Context's interest was unchanged, so it was not
+    /// reregistered
+    pub reregisters_saved: u64,
+    /// inbound connections turned away because max_connections had been reached
+    pub rejected: u64,
+    /// notifies and timeouts dropped because the Context they were for has gone,
+    /// including any sent with a Token from before its slot was reused
+    pub dropped_messages: u64
 }
 
 pub struct ReactorState<'a> {
     pub listeners: Slab<ListenRec<'a>>,
-    pub conns: Slab<ConnRec<'a>>,
-    pub timeouts: Slab<(TimerRec<'a>)>,
+    pub conns: ConnSlab<'a>,
+    pub timeouts: Slab<TimerRec<'a>>,
     pub config: ReactorConfig,
+    pub timer_serial: u64,
+    /// the timer slots owned by each Context, see `ReactorConfig::timers_per_connection`
+    pub conn_timers: HashMap<Token, Vec<Token>>,
+    /// listeners which are not to accept, and are not registered with the event loop
+    pub paused_listeners: HashSet<Token>,
+    /// the Context whose on_event is currently running, if any
+    pub current: Option<Token>,
+    /// set by close_current, the current Context is dropped once on_event returns
+    pub closing: bool,
+    pub error_handler: Option<Box<ErrorHandler<'a>>>,
+    pub overload_handler: Option<Box<OverloadHandler<'a>>>,
+    pub resolvers: Vec<Box<Resolver>>,
+    /// the interest each connected Context is registered with
+    pub interests: HashMap<Token, EventSet>,
+    /// what each connected Context's get_interest returned when it was last asked
+    pub reported_interests: HashMap<Token, EventSet>,
+    /// set by set_interest on the current Context, which is reregistered once on_event returns
+    pub interest_dirty: bool,
+    pub stats: ReactorStats,
+    /// the handlers and retry state of connected reconnecting connections, by token
+    pub reconnects: HashMap<Token, Connect<'a>>,
+    /// xorshift state for backoff jitter
+    pub rng: u64,
+    pub late_resolutions: LateResolutions,
 }
 
 impl<'a> ReactorState<'a> {
 
     pub fn new(cfg: ReactorConfig) -> ReactorState<'a> {
         let num_listeners = 255;
-        let conn_slots = cfg.max_connections + num_listeners + 1;
-        let timer_slots = conn_slots * cfg.timers_per_connection;
+        let conn_slots = cmp::min(cfg.initial_connections, cfg.max_connections);
+        let timer_slots = cfg.timer_slots(conn_slots);
 
         ReactorState {
             listeners: Slab::new_starting_at(Token(0), 255),
-            conns: Slab::new_starting_at(Token(num_listeners + 1), conn_slots),
+            conns: ConnSlab::new_starting_at(Token(num_listeners + 1), conn_slots),
             timeouts: Slab::new_starting_at(Token(0), timer_slots),
             config: cfg,
+            timer_serial: 0,
+            conn_timers: HashMap::new(),
+            paused_listeners: HashSet::new(),
+            current: None,
+            closing: false,
+            error_handler: None,
+            overload_handler: None,
+            resolvers: vec![Box::new(SystemResolver::new(2)) as Box<Resolver>],
+            interests: HashMap::new(),
+            reported_interests: HashMap::new(),
+            interest_dirty: false,
+            stats: ReactorStats::default(),
+            reconnects: HashMap::new(),
+            rng: precise_time_ns() | 1,
+            late_resolutions: Arc::new(Mutex::new(Vec::new())),
+        }
+    }
+
+    /// Take a slot for a connection, growing the conns slab if it is full, up to max_connections
+    pub fn insert_conn(&mut self, rec: ConnRec<'a>) -> Result<Token> {
+        if !self.conns.make_room(self.config.max_connections) {
+            return Err(Error::new(ErrorKind::Other, "Connection limit reached"));
+        }
+        self.conns.insert(rec).map_err(|_|Error::new(ErrorKind::Other, "Failed to insert into slab"))
+    }
+
+    /// Take a slot for a timer, growing the timeouts slab if it is full, up to
+    /// `ReactorConfig::timer_slots` for max_connections
+    pub fn insert_timer(&mut self, rec: TimerRec<'a>) -> Result<Token> {
+        let cap = self.config.timer_slots(self.config.max_connections);
+        if !make_room(&mut self.timeouts, cap) {
+            return Err(Error::new(ErrorKind::Other, "Timer limit reached"));
+        }
+        self.timeouts.insert(rec).map_err(|_|Error::new(ErrorKind::Other, "Failed to insert into slab"))
+    }
+
+    /// Drop everything kept on behalf of a Context which has gone away, cancelling
+    /// its timers so none fire into whatever takes its token next
+    pub fn forget(&mut self, event_loop: &mut EventLoop<ReactorHandler<'a>>, token: Token) {
+        for tok in self.conn_timers.remove(&token).unwrap_or_default() {
+            if let Some(TimerRec { timeout: Some(t), .. }) = self.timeouts.remove(tok) {
+                event_loop.clear_timeout(t);
+            }
+        }
+        self.interests.remove(&token);
+        self.reported_interests.remove(&token);
+        self.reconnects.remove(&token);
+    }
+
+    /// Note the interest a Context has just been registered with, from its get_interest
+    pub fn track_interest(&mut self, token: Token, interest: EventSet) {
+        self.interests.insert(token, interest);
+        self.reported_interests.insert(token, interest);
+    }
+
+    /// A pseudo-random number, good enough for jitter but nothing else
+    pub fn next_random(&mut self) -> u64 {
+        self.rng ^= self.rng << 13;
+        self.rng ^= self.rng >> 7;
+        self.rng ^= self.rng << 17;
+        self.rng
+    }
+
+    /// Log an error which occurred inside the event loop, where there is no caller to
+    /// return it to, and pass it on to the error handler if one has been set
+    pub fn report_error(&mut self, token: Option<Token>, err: Error) {
+        error!("reactor error for {:?}: {}", token, err);
+        if let Some(ref mut handler) = self.error_handler {
+            handler(token, err);
+        }
+    }
+
+    /// Release a timer slot, and the owning Context's share of timers_per_connection
+    pub fn remove_timer(&mut self, tok: Token) -> Option<TimerRec<'a>> {
+        let rec = self.timeouts.remove(tok);
+        if let Some(owner) = rec.as_ref().and_then(|r| r.owner) {
+            let empty = match self.conn_timers.get_mut(&owner) {
+                Some(timers) => {
+                    timers.retain(|t| *t != tok);
+                    timers.is_empty()
+                },
+                None => false
+            };
+            if empty {
+                self.conn_timers.remove(&owner);
+            }
+        }
+        rec
+    }
+
+    /// true if the timer referred to by handle has neither fired nor been cancelled
+    pub fn timer_live(&self, handle: &TimerHandle) -> bool {
+        self.timeouts.get(handle.token).map_or(false, |rec| rec.serial == handle.serial)
+    }
+}
+
+/// Outbound connections and timers. These are driven both by `ReactorCtrl` and by the
+/// event loop handler, and take the event loop alongside the state.
+impl<'a> ReactorState<'a> {
+
+    fn start_connect(&mut self,
+                     event_loop: &mut EventLoop<ReactorHandler<'a>>,
+                     hostname: &str,
+                     port: u16,
+                     resolver: ResolverId,
+                     timeout: Option<u64>,
+                     backoff: Option<Backoff>,
+                     handler: Box<ConnHandler<'a>>) -> Result<Token>
+    {
+        if resolver.0 >= self.resolvers.len() {
+            return Err(Error::new(ErrorKind::InvalidInput, "No such resolver"));
+        }
+
+        let tok = try!(self.insert_conn(ConnRec::None));
+
+        let mut conn = Connect {
+            handler: handler,
+            addrs: VecDeque::new(),
+            attempts: Vec::new(),
+            deadline: None,
+            stagger: None,
+            error: None,
+            retry: backoff.map(|b| Retry {
+                host: hostname.to_owned(),
+                port: port,
+                resolver: resolver,
+                backoff: b,
+                failures: 0,
+                timer: None
+            })
+        };
+
+        if let Some(ms) = timeout {
+            match self.add_timer(event_loop, ms, TimerTarget::Connect(tok), None) {
+                Ok(t) => { conn.deadline = Some(t); },
+                Err(e) => {
+                    self.conns.remove(tok);
+                    return Err(e);
+                }
+            }
+        }
+
+        match self.dial(event_loop, tok, conn, hostname, port, resolver) {
+            Ok(_) => Ok(tok),
+            // a reconnecting connection just tries again later
+            Err((mut conn, e)) => if conn.retry.is_some() {
+                conn.error = Some(e);
+                self.fail_connect(event_loop, tok, conn);
+                Ok(tok)
+            }
+            else {
+                self.abandon_connect(event_loop, &mut conn);
+                self.conns.remove(tok);
+                Err(e)
+            }
+        }
+    }
+
+    /// Start the outbound connection at token on its way, to hostname directly if it is
+    /// an address, which can fail immediately, or via the resolver otherwise
+    fn dial(&mut self,
+            event_loop: &mut EventLoop<ReactorHandler<'a>>,
+            tok: Token,
+            mut conn: Connect<'a>,
+            hostname: &str,
+            port: u16,
+            resolver: ResolverId) -> result::Result<(), (Connect<'a>, Error)>
+    {
+        match hostname.parse::<IpAddr>() {
+            Ok(ip) => {
+                match self.open_attempt(event_loop, tok, &SocketAddr::new(ip, port)) {
+                    Ok(atok) => {
+                        conn.attempts.push(atok);
+                        self.conns.replace(tok, ConnRec::Pending(conn));
+                        Ok(())
+                    },
+                    Err(e) => Err((conn, e))
+                }
+            },
+            Err(_) => {
+                self.conns.replace(tok, ConnRec::Resolving(conn));
+                let done = Resolution::new(tok, event_loop.channel(), self.late_resolutions.clone());
+                self.resolvers[resolver.0].resolve(hostname, port, done);
+                Ok(())
+            }
+        }
+    }
+
+    /// The backoff for the reconnecting connection at token is over, try again
+    pub(crate) fn redial(&mut self, event_loop: &mut EventLoop<ReactorHandler<'a>>, token: Token)
+    {
+        let mut conn = match self.conns.replace(token, ConnRec::None) {
+            Some(ConnRec::Waiting(conn)) => conn,
+            Some(rec) => {
+                self.conns.replace(token, rec);
+                return;
+            },
+            None => return
+        };
+        let (host, port, resolver) = match conn.retry {
+            Some(ref mut r) => {
+                r.timer = None;
+                (r.host.clone(), r.port, r.resolver)
+            },
+            None => return
+        };
+        debug!("Reconnecting {:?} to {}:{}", token, host, port);
+        if let Err((mut conn, e)) = self.dial(event_loop, token, conn, &host, port, resolver) {
+            conn.error = Some(e);
+            self.fail_connect(event_loop, token, conn);
+        }
+    }
+
+    /// The connected reconnecting connection at token has been lost, its Context
+    /// already dropped. Its timers are cancelled, so that none fire into the Context
+    /// of the next connection, which has the same token. Then wait out the backoff
+    /// and connect again.
+    pub(crate) fn disconnected(&mut self, event_loop: &mut EventLoop<ReactorHandler<'a>>, token: Token)
+    {
+        if let Some(mut conn) = self.reconnects.remove(&token) {
+            self.forget(event_loop, token);
+            conn.error = Some(Error::new(ErrorKind::ConnectionAborted, "Connection lost"));
+            self.fail_connect(event_loop, token, conn);
+        }
+    }
+
+    /// Begin a non-blocking connect to addr in a slot of its own, on behalf of the
+    /// outbound connection at token
+    fn open_attempt(&mut self,
+                    event_loop: &mut EventLoop<ReactorHandler<'a>>,
+                    token: Token,
+                    addr: &SocketAddr) -> Result<Token>
+    {
+        let sock = try!(TcpStream::connect(addr));
+        let atok = try!(self.insert_conn(ConnRec::None));
+        if let Err(e) = event_loop.register(&sock, atok, EventSet::writable(), PollOpt::edge()) {
+            self.conns.remove(atok);
+            return Err(e);
+        }
+        if self.conns.replace(atok, ConnRec::Attempt(sock, token)).is_none() {
+            return Err(Error::new(ErrorKind::Other, "Stale connection token"));
+        }
+        Ok(atok)
+    }
+
+    /// The resolver has answered for the outbound connection at token
+    pub(crate) fn resolved(&mut self,
+                           event_loop: &mut EventLoop<ReactorHandler<'a>>,
+                           token: Token,
+                           result: Result<Vec<SocketAddr>>)
+    {
+        let mut conn = match self.conns.replace(token, ConnRec::None) {
+            Some(ConnRec::Resolving(conn)) => conn,
+            Some(rec) => {
+                debug!("Dropping resolution for {:?}, it is no longer resolving", token);
+                self.conns.replace(token, rec);
+                return;
+            },
+            None => {
+                debug!("Dropping resolution for {:?}, the connection has gone away", token);
+                return;
+            }
+        };
+        match result {
+            Ok(addrs) => {
+                conn.addrs = interleave(addrs);
+                if conn.addrs.is_empty() {
+                    conn.error = Some(Error::new(ErrorKind::NotFound, "Host has no addresses"));
+                }
+            },
+            Err(e) => { conn.error = Some(e); }
+        }
+        self.conns.replace(token, ConnRec::Pending(conn));
+        self.next_attempt(event_loop, token);
+    }
+
+    /// Start connecting to the next untried address for the outbound connection
+    /// at token. If there is nothing left to try and nothing in flight, it has failed.
+    pub(crate) fn next_attempt(&mut self, event_loop: &mut EventLoop<ReactorHandler<'a>>, token: Token)
+    {
+        let mut conn = match self.conns.replace(token, ConnRec::None) {
+            Some(ConnRec::Pending(conn)) => conn,
+            Some(rec) => {
+                self.conns.replace(token, rec);
+                return;
+            },
+            None => return
+        };
+
+        if let Some(t) = conn.stagger.take() {
+            self.cancel_timer(event_loop, t);
+        }
+        while let Some(addr) = conn.addrs.pop_front() {
+            match self.open_attempt(event_loop, token, &addr) {
+                Ok(atok) => {
+                    debug!("Connecting {:?} to {}", token, addr);
+                    conn.attempts.push(atok);
+                    break;
+                },
+                Err(e) => {
+                    debug!("Failed to start connecting {:?} to {}: {}", token, addr, e);
+                    conn.error = Some(e);
+                }
+            }
+        }
+
+        if conn.attempts.is_empty() {
+            self.fail_connect(event_loop, token, conn);
+            return;
+        }
+        if !conn.addrs.is_empty() {
+            let delay = self.config.connect_attempt_delay;
+            conn.stagger = self.add_timer(event_loop, delay, TimerTarget::Stagger(token), None).ok();
+        }
+        self.conns.replace(token, ConnRec::Pending(conn));
+    }
+
+    /// An attempt on behalf of the outbound connection at token has finished.
+    /// On success the rest are abandoned and the socket is handed to the connect
+    /// handler, on failure the next address is tried.
+    pub(crate) fn attempt_done(&mut self,
+                               event_loop: &mut EventLoop<ReactorHandler<'a>>,
+                               token: Token,
+                               atok: Token,
+                               result: Result<(TcpStream, SocketAddr)>)
+    {
+        let mut conn = match self.conns.replace(token, ConnRec::None) {
+            Some(ConnRec::Pending(conn)) => conn,
+            Some(rec) => {
+                self.conns.replace(token, rec);
+                return;
+            },
+            None => return
+        };
+        conn.attempts.retain(|t| *t != atok);
+
+        match result {
+            Ok((sock, peeraddr)) => {
+                self.abandon_connect(event_loop, &mut conn);
+                let ctx = (conn.handler)(ConnResult::Connected(sock, token, peeraddr),
+                                         &mut ReactorCtrl::new(self, event_loop));
+                if let Some(ctx) = ctx {
+                    let interest = ctx.get_interest();
+                    match event_loop.register(ctx.get_evented(),
+                            token, interest | EventSet::hup(), ctx.get_poll_opt()) {
+                        Ok(_) => {
+                            self.conns.replace(token, ConnRec::Connected(ctx));
+                            self.track_interest(token, interest);
+                            if conn.retry.is_some() {
+                                if let Some(ref mut r) = conn.retry {
+                                    r.failures = 0;
+                                }
+                                conn.error = None;
+                                self.reconnects.insert(token, conn);
+                            }
+                        },
+                        Err(e) => {
+                            self.conns.remove(token);
+                            self.forget(event_loop, token);
+                            self.report_error(Some(token), e);
+                        }
+                    }
+                }
+                else {
+                    // the handler may have set timers for the token before turning it down
+                    self.conns.remove(token);
+                    self.forget(event_loop, token);
+                    debug!("Outbound connection to {} rejected", peeraddr);
+                }
+            },
+            Err(e) => {
+                debug!("Connection attempt for {:?} failed: {}", token, e);
+                conn.error = Some(e);
+                self.conns.replace(token, ConnRec::Pending(conn));
+                self.next_attempt(event_loop, token);
+            }
+        }
+    }
+
+    /// Give up on the outbound connection at token, and tell its handler why,
+    /// unless it is reconnecting and its backoff allows another retry
+    pub(crate) fn fail_connect(&mut self,
+                               event_loop: &mut EventLoop<ReactorHandler<'a>>,
+                               token: Token,
+                               mut conn: Connect<'a>)
+    {
+        self.abandon_connect(event_loop, &mut conn);
+
+        if let Some(delay) = self.retry_delay(&mut conn) {
+            debug!("Connection {:?} failed: {:?}, retrying in {}ms",
+                   token, conn.error, delay);
+            match self.add_timer(event_loop, delay, TimerTarget::Reconnect(token), None) {
+                Ok(t) => {
+                    conn.retry.as_mut().unwrap().timer = Some(t);
+                    self.conns.replace(token, ConnRec::Waiting(conn));
+                    return;
+                },
+                Err(e) => { conn.error = Some(e); }
+            }
+        }
+
+        self.conns.remove(token);
+        let err = conn.error.take()
+            .unwrap_or_else(|| Error::new(ErrorKind::Other, "No addresses to connect to"));
+        (conn.handler)(ConnResult::Failed(err), &mut ReactorCtrl::new(self, event_loop));
+    }
+
+    /// Count a failure against a reconnecting connection, returning how long to wait
+    /// before the next retry or None if there are to be no more
+    fn retry_delay(&mut self, conn: &mut Connect<'a>) -> Option<u64>
+    {
+        let random = self.next_random();
+        match conn.retry {
+            Some(ref mut r) => {
+                if let Some(max) = r.backoff.max_retries {
+                    if r.failures >= max {
+                        return None;
+                    }
+                }
+                r.failures += 1;
+                Some(r.backoff.delay(r.failures, random))
+            },
+            None => None
+        }
+    }
+
+    /// Cancel the timers and close the attempts in flight for an outbound connection
+    fn abandon_connect(&mut self, event_loop: &mut EventLoop<ReactorHandler<'a>>, conn: &mut Connect<'a>)
+    {
+        if let Some(t) = conn.deadline.take() {
+            self.cancel_timer(event_loop, t);
+        }
+        if let Some(t) = conn.stagger.take() {
+            self.cancel_timer(event_loop, t);
+        }
+        if let Some(t) = conn.retry.as_mut().and_then(|r| r.timer.take()) {
+            self.cancel_timer(event_loop, t);
+        }
+        for atok in conn.attempts.drain(..) {
+            if let Some(ConnRec::Attempt(sock, _)) = self.conns.remove(atok) {
+                if let Err(e) = event_loop.deregister(&sock) {
+                    debug!("Failed to deregister abandoned attempt {:?}: {}", atok, e);
+                }
+            }
+        }
+    }
+
+    pub(crate) fn add_timer(&mut self,
+                            event_loop: &mut EventLoop<ReactorHandler<'a>>,
+                            duration: u64,
+                            target: TimerTarget<'a>,
+                            schedule: Option<Schedule>) -> Result<TimerHandle>
+    {
+        let owner = match target {
+            TimerTarget::Conn(ctxtok, _) => Some(ctxtok),
+            _ => None
+        };
+        if let Some(ctxtok) = owner {
+            let live = self.conn_timers.get(&ctxtok).map_or(0, |timers| timers.len());
+            if live >= self.config.timers_per_connection {
+                return Err(Error::new(ErrorKind::Other, "Context already has timers_per_connection timers pending"));
+            }
+        }
+
+        self.timer_serial += 1;
+        let serial = self.timer_serial;
+        let interval = schedule.map(|s| Interval { period: duration, schedule: s, deadline: now_ms() + duration });
+        let tok = try!(self.insert_timer(TimerRec { target: Some(target),
+                                                    owner: owner,
+                                                    timeout: None,
+                                                    interval: interval,
+                                                    serial: serial }));
+        if let Some(ctxtok) = owner {
+            self.conn_timers.entry(ctxtok).or_insert_with(Vec::new).push(tok);
+        }
+        match event_loop.timeout_ms(tok.0, duration) {
+            Ok(t) => {
+                self.timeouts[tok].timeout = Some(t);
+                Ok(TimerHandle { token: tok, serial: serial })
+            },
+            Err(e) => {
+                self.remove_timer(tok);
+                Err(Error::new(ErrorKind::Other, format!("Failed to set timeout: {:?}", e)))
+            }
+        }
+    }
+
+    /// Cancel a pending timer, see `ReactorCtrl::cancel_timeout`
+    fn cancel_timer(&mut self, event_loop: &mut EventLoop<ReactorHandler<'a>>, handle: TimerHandle) -> bool {
+        if !self.timer_live(&handle) {
+            return false;
+        }
+        if let Some(rec) = self.remove_timer(handle.token) {
+            if let Some(t) = rec.timeout {
+                event_loop.clear_timeout(t);
+            }
         }
+        true
+    }
+}
+
+/// Make room for one more entry in slab if it is full, doubling it but keeping it
+/// to at most cap entries. Returns false if it is full and already at cap.
+fn make_room<T>(slab: &mut Slab<T>, cap: usize) -> bool {
+    if slab.has_remaining() {
+        return true;
+    }
+    let len = slab.count();
+    if len >= cap {
+        return false;
     }
+    slab.grow(cmp::min(cmp::max(len, 64), cap - len));
+    true
+}
+
+/// Monotonic clock in milliseconds, used to keep interval timers on schedule
+pub fn now_ms() -> u64 {
+    precise_time_ns() / 1_000_000
+}
+
+/// Order addresses for connecting, alternating between address families and
+/// starting with the family of the first, as RFC 8305 suggests
+fn interleave(addrs: Vec<SocketAddr>) -> VecDeque<SocketAddr> {
+    let first_v6 = match addrs.first() {
+        Some(&SocketAddr::V6(_)) => true,
+        _ => false
+    };
+    let (mut v6, mut v4) : (VecDeque<SocketAddr>, VecDeque<SocketAddr>) =
+        addrs.into_iter().partition(|a| match *a { SocketAddr::V6(_) => true, _ => false });
+    let (first, second) = if first_v6 { (&mut v6, &mut v4) } else { (&mut v4, &mut v6) };
+
+    let mut ordered = VecDeque::with_capacity(first.len() + second.len());
+    loop {
+        match (first.pop_front(), second.pop_front()) {
+            (None, None) => break,
+            (a, b) => {
+                ordered.extend(a);
+                ordered.extend(b);
+            }
+        }
+    }
+    ordered
 }
 
 /// ReactorCtrl is the event-loop control interface which is passed to every
@@ -97,27 +1058,60 @@ impl<'a, 'b : 'a> ReactorCtrl<'a, 'b> {
     /// return immediately.  It requires a handler, to which it will supply a `ConnResult` which
     /// will indicate success or failure. On success, it will supply a socket, a token, and a
     /// remote IP addr. It then expects an Option<Box<`Context`>> so that it can manage its events
+    ///
+    /// Host names are looked up with the given resolver, which delivers its answer through the
+    /// event loop, so a failed lookup is reported to the handler rather than returned from here.
+    /// IP addresses are connected to directly.
+    ///
+    /// When a host resolves to several addresses, they are all tried, alternating between
+    /// IPv6 and IPv4, with a new attempt started whenever one fails or has been outstanding
+    /// for `connect_attempt_delay`. The handler sees only the first attempt to succeed, or
+    /// the error from the last one to fail, and always under the returned token.
     pub fn connect<'c>(&mut self,
                    hostname: &'c str,
-                   port: usize,
+                   port: u16,
+                   resolver: ResolverId,
                    handler: Box<ConnHandler<'b>>) -> Result<Token>
     {
-        let saddr = try!(lookup_host(hostname).and_then(|ref mut lh| lh.nth(0)
-                            .ok_or(Error::last_os_error()))
-                        .and_then(move |sa| { match sa {
-                            Ok(SocketAddr::V4(sa4)) =>
-                                Ok(SocketAddr::V4(SocketAddrV4::new(*sa4.ip(), port as u16))),
-                            Ok(SocketAddr::V6(sa6)) =>
-                                Ok(SocketAddr::V6(SocketAddrV6::new(*sa6.ip(), port as u16, 0, 0))),
-                            Err(_) => return Err(Error::new(ErrorKind::Other,
-                                "Failed to parse Supplied socket address"))
-                        }}));
-        let sock = try!(TcpStream::connect(&saddr));
-        let tok = try!(self.state.conns.insert(ConnRec::None)
-                .map_err(|_|Error::new(ErrorKind::Other, "Failed to insert into slab")));
-        try!(self.event_loop.register(&sock, tok, EventSet::writable(), PollOpt::edge()));
-        self.state.conns[tok] = ConnRec::Pending(sock, handler);
-        Ok(tok)
+        self.state.start_connect(self.event_loop, hostname, port, resolver, None, None, handler)
+    }
+
+    /// As `connect`, but if the connection has not been established within timeout
+    /// milliseconds, including the time taken to resolve hostname, it is abandoned
+    /// and the handler receives `ConnResult::Failed` with an error of kind `TimedOut`
+    pub fn connect_timeout<'c>(&mut self,
+                   hostname: &'c str,
+                   port: u16,
+                   resolver: ResolverId,
+                   timeout: u64,
+                   handler: Box<ConnHandler<'b>>) -> Result<Token>
+    {
+        self.state.start_connect(self.event_loop, hostname, port, resolver, Some(timeout), None, handler)
+    }
+
+    /// As `connect`, but the connection is kept up: whenever an attempt fails, or the
+    /// Context is sent `Disconnect`, a new connection is made after waiting as backoff
+    /// says. The handler is called with `Connected` for every connection made, so it can
+    /// build a fresh Context each time, and only with `Failed` once backoff gives up.
+    ///
+    /// The returned token stays the same across reconnects, so it can be handed out for
+    /// `notify`, though anything sent while there is no connection is dropped. Closing
+    /// or deregistering the token, other than in response to `Disconnect`, or rejecting
+    /// a connection in the handler, stops it reconnecting.
+    pub fn reconnect<'c>(&mut self,
+                   hostname: &'c str,
+                   port: u16,
+                   resolver: ResolverId,
+                   backoff: Backoff,
+                   handler: Box<ConnHandler<'b>>) -> Result<Token>
+    {
+        self.state.start_connect(self.event_loop, hostname, port, resolver, None, Some(backoff), handler)
+    }
+
+    /// Hand a Resolver to the reactor for use with `connect`
+    pub fn add_resolver(&mut self, resolver: Box<Resolver>) -> ResolverId {
+        self.state.resolvers.push(resolver);
+        ResolverId(self.state.resolvers.len() - 1)
     }
 
     /// Listen on the supplied IP address:port for incoming TCP connections.  This returns
@@ -131,62 +1125,241 @@ impl<'a, 'b : 'a> ReactorCtrl<'a, 'b> {
         let server = try!(TcpListener::bind(&saddr));
         let tok = try!(self.state.listeners.insert(Some((server,handler)))
                 .map_err(|_|Error::new(ErrorKind::Other, "Failed to insert into slab")));
+        // listeners are always edge triggered, accept drains the backlog on every event
         if let &mut Some((ref server, _)) = self.state.listeners.get_mut(tok).unwrap() {
             try!(self.event_loop.register(server, tok, EventSet::readable(), PollOpt::edge()));
         }
         Ok(tok)
     }
 
+    /// The address the listener for token is bound to, which tells the port the OS
+    /// picked for a listener on port 0. Fails for a token which is not a listener,
+    /// and from within that listener's own handler, while it is accepting.
+    pub fn local_addr(&self, token: Token) -> Result<SocketAddr>
+    {
+        match self.state.listeners.get(token) {
+            Some(&Some((ref server, _))) => server.local_addr(),
+            Some(&None) => Err(Error::new(ErrorKind::Other, "Cannot get the address of a listener from within its own handler")),
+            None => Err(Error::new(ErrorKind::Other, "No listener for Token"))
+        }
+    }
+
+    /// A handle for running closures on the event loop from other threads
+    pub fn remote(&self) -> RemoteHandle {
+        RemoteHandle::new(self.event_loop.channel())
+    }
+
+    /// Stop listening on the listener for token, closing its socket, and get back
+    /// the handler it was given. This cannot be done from within that handler.
+    pub fn unlisten(&mut self, token: Token) -> Result<Box<ConnHandler<'b>>>
+    {
+        match self.state.listeners.get(token) {
+            Some(&Some(_)) => {},
+            Some(&None) => return Err(Error::new(ErrorKind::Other, "Cannot unlisten a listener from within its own handler")),
+            None => return Err(Error::new(ErrorKind::Other, "No listener for Token"))
+        }
+        let (server, handler) = self.state.listeners.remove(token).unwrap().unwrap();
+        // a paused listener is already deregistered
+        if !self.state.paused_listeners.remove(&token) {
+            if let Err(e) = self.event_loop.deregister(&server) {
+                debug!("Failed to deregister listener {:?}: {}", token, e);
+            }
+        }
+        Ok(handler)
+    }
+
+    /// Stop accepting connections on the listener for token until `resume_listener`.
+    /// New connections wait in the listen backlog meanwhile, or are refused by the OS
+    /// once it is full. Called from the listener's own handler, no more connections
+    /// are accepted this tick.
+    pub fn pause_listener(&mut self, token: Token) -> Result<()>
+    {
+        if !self.state.listeners.contains(token) {
+            return Err(Error::new(ErrorKind::Other, "No listener for Token"));
+        }
+        if !self.state.paused_listeners.insert(token) {
+            return Ok(());
+        }
+        // while its handler is running the listener is out of the slab, and is
+        // deregistered once it has finished accepting
+        if let Some(&Some((ref server, _))) = self.state.listeners.get(token) {
+            if let Err(e) = self.event_loop.deregister(server) {
+                self.state.paused_listeners.remove(&token);
+                return Err(e);
+            }
+        }
+        Ok(())
+    }
+
+    /// Start accepting connections again on a listener paused by `pause_listener`,
+    /// including any which queued up while it was paused
+    pub fn resume_listener(&mut self, token: Token) -> Result<()>
+    {
+        if !self.state.listeners.contains(token) {
+            return Err(Error::new(ErrorKind::Other, "No listener for Token"));
+        }
+        if !self.state.paused_listeners.remove(&token) {
+            return Ok(());
+        }
+        if let Some(&Some((ref server, _))) = self.state.listeners.get(token) {
+            if let Err(e) = self.event_loop.register(server, token, EventSet::readable(), PollOpt::edge()) {
+                self.state.paused_listeners.insert(token);
+                return Err(e);
+            }
+        }
+        Ok(())
+    }
+
     /// fetch the event_loop channel for notifying the event_loop of new outbound data
-    pub fn channel(&self) -> Sender<TaggedBuf> {
-        self.event_loop.channel()
+    pub fn channel(&self) -> Notifier {
+        Notifier::new(self.event_loop.channel())
     }
 
     /// Set a timeout to be executed by the event loop after duration
     /// Minimum expected resolution is the tick duration of the event loop
     /// poller, but it could be shorted depending on how many events are
     /// occurring
-    pub fn timeout(&mut self, duration: u64, handler: Box<TimeoutHandler<'b>>) -> TimerResult<(Timeout, Token)> {
-        let tok = self.state.timeouts.insert((None, Some(handler))).map_err(|_| format!("failed")).unwrap();
-        let handle = try!(self.event_loop.timeout_ms(tok.0, duration));
-        Ok((handle, tok))
+    pub fn timeout(&mut self, duration: u64, handler: Box<TimeoutHandler<'b>>) -> Result<TimerHandle> {
+        self.state.add_timer(self.event_loop, duration, TimerTarget::Handler(handler), None)
     }
 
     /// Set a timeout to be executed by the handler of a Context for a given token.
     /// This is useful for protocols which have timeouts or timed ping/pongs such as IRC.
-    pub fn timeout_conn(&mut self, duration: u64, ctxtok: Token) -> TimerResult<(Timeout, Token)> {
-        let tok = self.state.timeouts.insert((Some(ctxtok),None)).map_err(|_| format!("failed")).unwrap();
-        let handle = try!(self.event_loop.timeout_ms(tok.0, duration));
-        Ok((handle, tok))
+    /// tag is handed back in `EventType::Timeout(tag)` so that a Context with several
+    /// timers pending can tell which one fired.
+    pub fn timeout_conn(&mut self, duration: u64, ctxtok: Token, tag: usize) -> Result<TimerHandle> {
+        self.state.add_timer(self.event_loop, duration, TimerTarget::Conn(ctxtok, tag), None)
     }
 
-    /// Supply a context to the event_loop for monitoring and get back a token
+    /// Invoke handler every period milliseconds until the returned handle is
+    /// passed to `cancel_timeout`. The first tick is due one period from now.
+    pub fn interval(&mut self, period: u64, schedule: Schedule, handler: Box<TimeoutHandler<'b>>) -> Result<TimerHandle> {
+        if period == 0 {
+            return Err(Error::new(ErrorKind::InvalidInput, "Interval period must be at least 1ms"));
+        }
+        self.state.add_timer(self.event_loop, period, TimerTarget::Handler(handler), Some(schedule))
+    }
+
+    /// Deliver `EventType::Timeout(tag)` to the Context for ctxtok every period
+    /// milliseconds until the returned handle is passed to `cancel_timeout`,
+    /// or the Context goes away.
+    pub fn interval_conn(&mut self, period: u64, schedule: Schedule, ctxtok: Token, tag: usize) -> Result<TimerHandle> {
+        if period == 0 {
+            return Err(Error::new(ErrorKind::InvalidInput, "Interval period must be at least 1ms"));
+        }
+        self.state.add_timer(self.event_loop, period, TimerTarget::Conn(ctxtok, tag), Some(schedule))
+    }
+
+    /// Cancel a pending timeout. Its handler will not be invoked and its
+    /// slot is released immediately. Returns false if the timer had already
+    /// fired or been cancelled.
+    pub fn cancel_timeout(&mut self, handle: TimerHandle) -> bool {
+        self.state.cancel_timer(self.event_loop, handle)
+    }
+
+    /// Push a pending timeout back so that it fires after duration from now
+    /// instead of at its original deadline. Returns Ok(false) if the timer
+    /// had already fired or been cancelled. An interval keeps its period and
+    /// carries on from the new deadline.
+    pub fn reschedule_timeout(&mut self, handle: &TimerHandle, duration: u64) -> Result<bool> {
+        if !self.state.timer_live(handle) {
+            return Ok(false);
+        }
+        if let Some(t) = self.state.timeouts[handle.token].timeout.take() {
+            self.event_loop.clear_timeout(t);
+        }
+        match self.event_loop.timeout_ms(handle.token.0, duration) {
+            Ok(t) => {
+                let rec = &mut self.state.timeouts[handle.token];
+                rec.timeout = Some(t);
+                if let Some(ref mut iv) = rec.interval {
+                    iv.deadline = now_ms() + duration;
+                }
+                Ok(true)
+            },
+            Err(e) => {
+                self.state.remove_timer(handle.token);
+                Err(Error::new(ErrorKind::Other, format!("Failed to reschedule timeout: {:?}", e)))
+            }
+        }
+    }
+
+    /// Supply a context to the event_loop for monitoring and get back a token.
+    /// It is registered with the poller using its `get_interest` and `get_poll_opt`
     pub fn register<C>(&mut self, ctx : C) -> Result<Token>
     where C : Context + 'static
     {
         let foo : Box<Context> = Box::new(ctx);
-        let token = try!(self.state.conns.insert(ConnRec::None)
-            .map_err(|_|Error::new(ErrorKind::Other, "Failed to insert into slab")));
+        let token = try!(self.state.insert_conn(ConnRec::None));
 
-        try!(self.event_loop.register(foo.get_evented() as &Evented, token, foo.get_interest(), PollOpt::edge()));
+        let interest = foo.get_interest();
+        if let Err(e) = self.event_loop.register(foo.get_evented() as &Evented, token, interest, foo.get_poll_opt()) {
+            self.state.conns.remove(token);
+            return Err(e);
+        }
 
-        self.state.conns[token] = ConnRec::Connected(foo);
+        if self.state.conns.replace(token, ConnRec::Connected(foo)).is_none() {
+            return Err(Error::new(ErrorKind::Other, "Stale connection token"));
+        }
+        self.state.track_interest(token, interest);
         Ok(token)
     }
 
+    /// Change the events the Context for token is registered for, without waiting for
+    /// it to handle an event and return a new interest from `get_interest`, which is
+    /// checked after every event. Called for the current Context, the change is
+    /// made once its on_event returns, otherwise it is made straight away. Setting the
+    /// interest it already has costs nothing.
+    pub fn set_interest(&mut self, token: Token, interest: EventSet) -> Result<()>
+    {
+        match self.state.interests.get(&token) {
+            Some(&current) if current == interest => return Ok(()),
+            Some(_) => {},
+            None => return Err(Error::new(ErrorKind::Other, "No context for Token"))
+        }
+        if self.state.current == Some(token) {
+            self.state.interests.insert(token, interest);
+            self.state.interest_dirty = true;
+            return Ok(());
+        }
+        match self.state.conns.get(token) {
+            Some(&ConnRec::Connected(ref ctx)) => {
+                try!(self.event_loop.reregister(ctx.get_evented(), token,
+                                                interest | EventSet::hup(), ctx.get_poll_opt()));
+            },
+            _ => return Err(Error::new(ErrorKind::Other, "Context for Token is being dispatched"))
+        }
+        self.state.interests.insert(token, interest);
+        self.state.stats.reregisters += 1;
+        Ok(())
+    }
+
+    /// The reactor's counters since it was created
+    pub fn stats(&self) -> ReactorStats {
+        self.state.stats
+    }
+
     /// deregister a context for a given token and receive back the context
     /// NOTE : You cannot deregister the context for a token while running in the
-    /// handler of that context. It must be called for a different context
+    /// handler of that context. Use `close_current` to drop it instead.
     pub fn deregister(&mut self, token: Token) -> Result<Box<Context>>
     {
+        if self.state.current == Some(token) {
+            return Err(Error::new(ErrorKind::Other, "Cannot deregister the current context, use close_current"));
+        }
+        // an attempt belongs to its pending connection, which will tidy it up
+        if let Some(&ConnRec::Attempt(..)) = self.state.conns.get(token) {
+            return Err(Error::new(ErrorKind::Other, "No context for Token"));
+        }
         if let Some(conn) = self.state.conns.remove(token) {
+            self.state.forget(self.event_loop, token);
             match conn {
                 ConnRec::Connected(ctx) => {
-                    self.event_loop.deregister(ctx.get_evented()).unwrap();
+                    try!(self.event_loop.deregister(ctx.get_evented()));
                     Ok(ctx)
                 }
-                ConnRec::Pending(sock, _) => {
-                    self.event_loop.deregister(&sock).unwrap();
+                ConnRec::Resolving(mut conn) | ConnRec::Pending(mut conn) | ConnRec::Waiting(mut conn) => {
+                    self.state.abandon_connect(self.event_loop, &mut conn);
                     Err(Error::new(ErrorKind::Other, "Connection for token was pending, no context to return"))
                 }
                 _ => {
@@ -199,6 +1372,45 @@ impl<'a, 'b : 'a> ReactorCtrl<'a, 'b> {
         }
     }
 
+    /// The token of the Context whose handler is currently running, if any
+    pub fn current(&self) -> Option<Token> {
+        self.state.current
+    }
+
+    /// Close the context for a given token: deregister its socket/evented from
+    /// the event_loop, release its token and drop it. Unlike `deregister` this may be
+    /// called for the current context from within its own handler, in which case it
+    /// takes effect once on_event returns and no further events are delivered to it.
+    pub fn close(&mut self, token: Token) -> Result<()>
+    {
+        if self.state.current == Some(token) {
+            self.state.closing = true;
+            return Ok(());
+        }
+        if !self.state.conns.contains(token) {
+            return Err(Error::new(ErrorKind::Other, "No context for Token"));
+        }
+        match self.deregister(token) {
+            Ok(_) => Ok(()),
+            // a pending connection has no context to hand back, but it is closed all the same
+            Err(e) => if self.state.conns.contains(token) { Err(e) } else { Ok(()) }
+        }
+    }
+
+    /// true if the current Context has asked to be closed once its on_event returns
+    pub(crate) fn closing(&self) -> bool {
+        self.state.closing
+    }
+
+    /// Close the context whose handler is currently running, see `close`
+    pub fn close_current(&mut self) -> Result<()>
+    {
+        match self.state.current {
+            Some(token) => self.close(token),
+            None => Err(Error::new(ErrorKind::Other, "No context is currently being dispatched"))
+        }
+    }
+
     /// calculates the 11th digit of pi
     pub fn shutdown(&mut self) {
         self.event_loop.shutdown();
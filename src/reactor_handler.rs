@@ -1,5 +1,8 @@
-use std::io::{Error, ErrorKind};
+use std::io::{Error, ErrorKind, Write};
+use std::mem;
+use std::net::SocketAddr;
 
+use mio::tcp::TcpStream;
 use mio::{Token,
           EventLoop,
           EventSet,
@@ -8,10 +11,19 @@ use mio::{Token,
 
 use context::{Context, EventType};
 use reactor_ctrl::{ReactorCtrl,
+                   ConnHandler,
                    ConnRec,
                    ConnResult,
                    ReactorState,
-                   TaggedBuf};
+                   TimerTarget,
+                   Schedule,
+                   CatchUp,
+                   ReactorMsg,
+                   now_ms};
+
+/// Milliseconds to wait before accepting again on a listener whose accept failed,
+/// which is most likely for want of file descriptors
+const ACCEPT_BACKOFF: u64 = 100;
 
 pub struct ReactorHandler<'a>
 {
@@ -19,55 +31,204 @@ pub struct ReactorHandler<'a>
 }
 
 impl<'a> ReactorHandler<'a> {
-    fn on_read(&mut self, event_loop: &mut EventLoop<ReactorHandler<'a>>, token: Token, evts : EventSet) {
+
+    /// Dispatch every readiness kind in `evts` to a connected Context, in a fixed order:
+    /// `Readable`, then `Writable`, then `Disconnect` if the poller reported a hangup or error.
+    fn on_ready(&mut self, event_loop: &mut EventLoop<ReactorHandler<'a>>, token: Token, evts : EventSet, mut ctx: Box<Context>) {
 
         let close = evts.is_hup() || evts.is_error();
         let mut state = self.state.as_mut().unwrap();
+        let mut open = true;
+
+        if evts.is_readable() {
+            open = deliver(&mut state, event_loop, token, &mut ctx, EventType::Readable);
+        }
+        if open && evts.is_writable() {
+            open = deliver(&mut state, event_loop, token, &mut ctx, EventType::Writable);
+        }
+        if open && close {
+            deliver(&mut state, event_loop, token, &mut ctx, EventType::Disconnect);
+        }
+        // a lost reconnecting connection is replaced rather than closed, whatever
+        // its Context made of the Disconnect
+        if close && state.reconnects.contains_key(&token) {
+            state.closing = false;
+            state.interest_dirty = false;
+            if let Err(e) = event_loop.deregister(ctx.get_evented()) {
+                debug!("Failed to deregister lost connection {:?}: {}", token, e);
+            }
+            drop(ctx);
+            state.disconnected(event_loop, token);
+            return;
+        }
+        restore(&mut state, event_loop, token, ctx, !close);
+    }
+
+    /// A connection attempt has become writable (connected) or has hung up
+    /// (failed). Hand the result to the outbound connection it belongs to.
+    fn on_attempt(&mut self,
+                  event_loop: &mut EventLoop<ReactorHandler<'a>>,
+                  token: Token,
+                  evts : EventSet,
+                  sock: TcpStream,
+                  parent: Token) {
+
+        let state = self.state.as_mut().unwrap();
+        let failed = evts.is_hup() || evts.is_error();
+
+        if !failed && !evts.is_writable() {
+            state.conns.replace(token, ConnRec::Attempt(sock, parent));
+            return;
+        }
+
+        // SO_ERROR tells us why the connect failed, some platforms report a failed
+        // connect as writable so it is checked on every outcome
+        let result = match sock.take_socket_error() {
+            Err(e) => Err(e),
+            Ok(_) if failed => Err(Error::new(ErrorKind::ConnectionAborted,
+                                              "Connection closed before it was established")),
+            // the peer may have gone away between connecting and us looking
+            Ok(_) => sock.peer_addr()
+        };
 
+        // the attempt's token is released, a connected socket is registered afresh
+        // under the token of the outbound connection
+        state.conns.remove(token);
+        if let Err(e) = event_loop.deregister(&sock) {
+            debug!("Failed to deregister connection attempt {:?}: {}", token, e);
+        }
+        state.attempt_done(event_loop, parent, token, result.map(|addr| (sock, addr)));
+    }
+
+    /// Deliver a Notify or Message sent through the channel to its Context
+    fn on_notify(&mut self, event_loop: &mut EventLoop<ReactorHandler<'a>>, token: Token, evt: EventType) {
+        let mut state = self.state.as_mut().unwrap();
         match state.conns.replace(token, ConnRec::None) {
-            Some(ConnRec::Pending(_, mut handler)) => {
-                if close {
-                    //TODO Add exact error message from sockopt
-                    handler(ConnResult::Failed(Error::new(ErrorKind::ConnectionRefused, "")), &mut ReactorCtrl::new(&mut state, event_loop));
-                    return;
-                }
-            },
             Some(ConnRec::Connected(mut ctx)) => {
-                ctx.on_event(&mut ReactorCtrl::new(&mut state, event_loop),
-                             EventType::Readable);
-                if close {
-                    ctx.on_event(&mut ReactorCtrl::new(&mut state, event_loop),
-                                 EventType::Disconnect);
-                }else {
-                    event_loop.reregister(ctx.get_evented(), token, ctx.get_interest() | EventSet::hup(), PollOpt::edge()).unwrap();
-                }
-                state.conns.replace(token, ConnRec::Connected(ctx));
+                deliver(&mut state, event_loop, token, &mut ctx, evt);
+                restore(&mut state, event_loop, token, ctx, true);
             },
-            _ => {panic!("Got a readable event for an unregistered token");}
+            Some(ConnRec::None) => {
+                warn!("Got a notify event for {:?} while it was being dispatched, dropping it", token);
+            },
+            Some(rec) => {
+                warn!("Got a notify event for pending connection {:?}, dropping it", token);
+                state.conns.replace(token, rec);
+            },
+            None => {
+                state.stats.dropped_messages += 1;
+                debug!("Got a notify event for a closed context {:?}, dropping it", token);
+            }
         }
     }
+}
 
-    fn accept(&mut self, event_loop: &mut EventLoop<ReactorHandler<'a>>, token: Token) {
+/// Accept waiting connections on the listener for token, handing each to its handler
+fn accept<'a>(state: &mut ReactorState<'a>,
+              event_loop: &mut EventLoop<ReactorHandler<'a>>,
+              token: Token) {
+    // taken out while it accepts, Slab::replace would count the slot as emptied
+    let (accpt, mut handler) = match state.listeners.get_mut(token).and_then(|r| mem::replace(r, None)) {
+        Some(rec) => rec,
+        _ => {
+            warn!("Got an accept event for a listener which is not present {:?}", token);
+            return;
+        }
+    };
 
-        let mut state = self.state.as_mut().unwrap();
-        if let Some((accpt, mut handler)) = state.listeners.replace(token, None).unwrap() {
-            if let Some((sock, peeraddr)) = accpt.accept().unwrap() {
-                event_loop.reregister(&accpt, token, EventSet::readable(), PollOpt::edge()).unwrap();
-
-                let newtok = state.conns.insert(ConnRec::None)
-                    .map_err(|_|Error::new(ErrorKind::Other, "Failed to insert into slab")).unwrap();
-                if let Some(ctx) = handler(ConnResult::Connected(sock, newtok, peeraddr),&mut ReactorCtrl::new(&mut state, event_loop)) {
-                    event_loop.register(ctx.get_evented(), newtok, ctx.get_interest() | EventSet::hup(), PollOpt::edge()).unwrap();
-                    state.conns.replace(newtok, ConnRec::Connected(ctx));
+    // listeners are edge triggered, so keep accepting until the backlog is drained,
+    // or until we've taken our share of this tick
+    let limit = state.config.accepts_per_tick;
+    let mut accepted = 0;
+    loop {
+        if state.paused_listeners.contains(&token) {
+            // the handler paused its own listener
+            if let Err(e) = event_loop.deregister(&accpt) {
+                debug!("Failed to deregister paused listener {:?}: {}", token, e);
+            }
+            break;
+        }
+        if limit != 0 && accepted == limit {
+            // there may be more waiting, but no new edge will arrive for them
+            // reregistering rearms the listener so that we come back next tick
+            if let Err(e) = event_loop.reregister(&accpt, token, EventSet::readable(), PollOpt::edge()) {
+                state.report_error(Some(token), e);
+            }
+            break;
+        }
+        match accpt.accept() {
+            Ok(Some((sock, peeraddr))) => {
+                accepted += 1;
+                admit(state, event_loop, token, &mut handler, sock, peeraddr);
+            },
+            Ok(None) => {
+                break;
+            },
+            Err(ref e) if e.kind() == ErrorKind::WouldBlock => {
+                break;
+            },
+            Err(ref e) if e.kind() == ErrorKind::Interrupted => {
+                continue;
+            },
+            Err(e) => {
+                // most likely EMFILE/ENFILE. Leave the rest in the backlog rather than
+                // spin on it. No new edge will arrive for connections which are already
+                // waiting, so come back for them once some descriptors may have been freed
+                state.report_error(Some(token), e);
+                if let Err(e) = state.add_timer(event_loop, ACCEPT_BACKOFF, TimerTarget::Accept(token), None) {
+                    state.report_error(Some(token), e);
                 }
-                else {
-                    debug!("Connection from {} rejected", peeraddr);
+                break;
+            }
+        }
+    }
+    if let Some(rec) = state.listeners.get_mut(token) {
+        *rec = Some((accpt, handler));
+    }
+}
+
+/// Hand a freshly accepted socket to the listener's handler, and register
+/// the Context it returns
+fn admit<'a>(state: &mut ReactorState<'a>,
+             event_loop: &mut EventLoop<ReactorHandler<'a>>,
+             token: Token,
+             handler: &mut Box<ConnHandler<'a>>,
+             sock: TcpStream,
+             peeraddr: SocketAddr) {
+    match state.insert_conn(ConnRec::None) {
+        Ok(newtok) => {
+            if let Some(ctx) = handler(ConnResult::Connected(sock, newtok, peeraddr), &mut ReactorCtrl::new(state, event_loop)) {
+                let interest = ctx.get_interest();
+                match event_loop.register(ctx.get_evented(), newtok, interest | EventSet::hup(), ctx.get_poll_opt()) {
+                    Ok(_) => {
+                        state.conns.replace(newtok, ConnRec::Connected(ctx));
+                        state.track_interest(newtok, interest);
+                    },
+                    Err(e) => {
+                        state.conns.remove(newtok);
+                        state.report_error(Some(newtok), e);
+                    }
                 }
             }
             else {
-                error!("{}", Error::last_os_error());
+                state.conns.remove(newtok);
+                debug!("Connection from {} rejected", peeraddr);
+            }
+        },
+        Err(_) => {
+            // at max_connections, turn the connection away as politely as we can
+            let mut sock = sock;
+            state.stats.rejected += 1;
+            warn!("At max_connections, rejecting connection from {}", peeraddr);
+            if let Some(ref resp) = state.config.overload_response {
+                if let Err(e) = sock.write(resp) {
+                    debug!("Failed to write overload response to {}: {}", peeraddr, e);
+                }
+            }
+            drop(sock);
+            if let Some(ref mut hook) = state.overload_handler {
+                hook(token, peeraddr);
             }
-            state.listeners.replace(token, Some((accpt, handler)));
         }
     }
 }
@@ -77,7 +238,7 @@ impl<'a> ReactorHandler<'a> {
 impl<'a> Handler for ReactorHandler<'a>
 {
     type Timeout = usize;
-    type Message = TaggedBuf;
+    type Message = ReactorMsg;
 
     /// Invoked when the socket represented by `token` is ready to be operated
     /// on. `events` indicates the specific operations that are
@@ -90,39 +251,33 @@ impl<'a> Handler for ReactorHandler<'a>
     /// This function will only be invoked a single time per socket per event
     /// loop tick.
     fn ready(&mut self, event_loop: &mut EventLoop<Self>, token: Token, events: EventSet) {
-        if events.is_readable() {
-            debug!("mio_processor::readable top, token: {:?}", token);
-            if self.state.as_ref().unwrap().listeners.contains(token) {
-                self.accept(event_loop, token);
-            } else {
-                self.on_read(event_loop, token, events);
-            }
-        }
-        else if events.is_writable() {
-            debug!("mio_processor::writable, token: {:?}", token);
-            let mut state = self.state.as_mut().unwrap();
-            match state.conns.replace(token, ConnRec::None) {
-                Some(ConnRec::Connected(mut ctx)) => {
-                    ctx.on_event(&mut ReactorCtrl::new(state, event_loop),
-                        EventType::Readable);
-                    event_loop.reregister(ctx.get_evented(), token,
-                        ctx.get_interest() | EventSet::hup(), PollOpt::edge()).unwrap();
-                    state.conns.replace(token, ConnRec::Connected(ctx));
-                },
-                Some(ConnRec::Pending(sock, mut handler)) => {
-                    let peeraddr = sock.peer_addr().unwrap();
-                    if let Some(ctx) = handler(ConnResult::Connected(sock, token, peeraddr), &mut ReactorCtrl::new(&mut state, event_loop)) {
-                        event_loop.reregister(ctx.get_evented(),
-                            token, ctx.get_interest() | EventSet::hup(), PollOpt::edge()).unwrap();
-                        state.conns.replace(token, ConnRec::Connected(ctx));
-                    }
-                    else {
-                        debug!("Outbound connection to {} rejected", peeraddr);
-                    }
-                },
-                _ => { panic!("Got a writable event for a non-present context") }
+        debug!("mio_processor::ready, token: {:?} events: {:?}", token, events);
+        if self.state.as_ref().unwrap().listeners.contains(token) {
+            // a listener paused earlier this tick may still have an event queued
+            if events.is_readable() && !self.state.as_ref().unwrap().paused_listeners.contains(&token) {
+                accept(self.state.as_mut().unwrap(), event_loop, token);
             }
+            return;
+        }
 
+        let rec = self.state.as_mut().unwrap().conns.replace(token, ConnRec::None);
+        match rec {
+            Some(ConnRec::Connected(ctx)) => {
+                self.on_ready(event_loop, token, events, ctx);
+            },
+            Some(ConnRec::Attempt(sock, parent)) => {
+                self.on_attempt(event_loop, token, events, sock, parent);
+            },
+            Some(rec @ ConnRec::Resolving(..)) | Some(rec @ ConnRec::Pending(..)) | Some(rec @ ConnRec::Waiting(..)) => {
+                warn!("Got an event for {:?} which has no socket of its own, dropping it", token);
+                self.state.as_mut().unwrap().conns.replace(token, rec);
+            },
+            Some(ConnRec::None) => {
+                warn!("Got an event for {:?} while it was being dispatched, dropping it", token);
+            },
+            None => {
+                warn!("Got an event for an unregistered token {:?}, dropping it", token);
+            }
         }
     }
 
@@ -131,23 +286,23 @@ impl<'a> Handler for ReactorHandler<'a>
     }
 
     /// Invoked at the end of an event loop tick.
-    fn tick(&mut self, _event_loop: &mut EventLoop<Self>) {
+    fn tick(&mut self, event_loop: &mut EventLoop<Self>) {
+        let state = self.state.as_mut().unwrap();
+        let late : Vec<_> = state.late_resolutions.lock().unwrap().drain(..).collect();
+        for (token, result) in late {
+            state.resolved(event_loop, token, result);
+        }
     }
 
 
-    fn notify(&mut self, event_loop: &mut EventLoop<ReactorHandler<'a>>, msg: TaggedBuf) {
-        let token = msg.0;
-        let mut state = self.state.as_mut().unwrap();
-        match state.conns.replace(token, ConnRec::None) {
-            Some(ConnRec::Connected(mut ctx)) => {
-                ctx.on_event(&mut ReactorCtrl::new(state, event_loop),
-                    EventType::Notify(msg.1));
-                event_loop.reregister(ctx.get_evented(), token,
-                    ctx.get_interest() | EventSet::hup(), PollOpt::edge()).unwrap();
-                state.conns.replace(token, ConnRec::Connected(ctx));
-            },
-            Some(ConnRec::Pending(_,_)) => { panic!("Got a notify event for a pending socket connection"); },
-            _ => { panic!("Got a notify event for a non-present context") }
+    fn notify(&mut self, event_loop: &mut EventLoop<ReactorHandler<'a>>, msg: ReactorMsg) {
+        match msg {
+            ReactorMsg::Notify((token, buf)) => self.on_notify(event_loop, token, EventType::Notify(buf)),
+            ReactorMsg::Message(token, msg) => self.on_notify(event_loop, token, EventType::Message(msg)),
+            ReactorMsg::Run(mut task) => task(&mut ReactorCtrl::new(self.state.as_mut().unwrap(), event_loop)),
+            ReactorMsg::Resolved(token, result) => {
+                self.state.as_mut().unwrap().resolved(event_loop, token, result)
+            }
         }
     }
 
@@ -155,32 +310,214 @@ impl<'a> Handler for ReactorHandler<'a>
 
         let tok = Token(timeout as usize);
         let mut state = self.state.as_mut().unwrap();
-        let mut rec = state.timeouts.remove(tok);
 
-        match rec {
-            Some((Some(tok), None)) => {
-                if let Some(mut conn) = state.conns.replace(tok, ConnRec::None) {
-                    match conn {
-                        ConnRec::Connected(ref mut ctx) => {
-                            ctx.on_event(&mut ReactorCtrl::new(&mut state, event_loop),
-                                EventType::Timeout(timeout))
-                        },
-                        ConnRec::Pending(_,_) => {
-                            panic!("Got a timeout event for a pending socket connection");
-                        },
-                        ConnRec::None => {
-                            panic!("Got a timeout event for a non-present context")
-                        }
-                    }
-                    state.conns.replace(tok, conn);
-                }
+        let (target, interval, serial) = match state.timeouts.get_mut(tok) {
+            Some(rec) => {
+                // this mio timeout has fired, a reschedule from within the handler will set a new one
+                rec.timeout = None;
+                (rec.target.take(), rec.interval, rec.serial)
             },
-            Some((None, Some(ref mut handler))) => {
-                handler(Token(timeout),&mut ReactorCtrl::new(&mut state, event_loop))
-            },
-            _ => {panic!("We shouldn't be here")}
+            None => {
+                warn!("Got a timeout for a timer which is not present {:?}", tok);
+                return;
+            }
+        };
+        let mut target = match target {
+            Some(target) => target,
+            None => {
+                warn!("Timer {:?} fired while it was being dispatched, dropping it", tok);
+                return;
+            }
+        };
+
+        let mut iv = match interval {
+            Some(iv) => iv,
+            None => {
+                state.remove_timer(tok);
+                fire_timer(&mut state, event_loop, tok, &mut target);
+                return;
+            }
+        };
+
+        let now = now_ms();
+        let behind = now.saturating_sub(iv.deadline) / iv.period;
+        let ticks = match iv.schedule {
+            Schedule::FixedRate(CatchUp::Burst) => behind + 1,
+            _ => 1
+        };
+
+        for _ in 0 .. ticks {
+            let present = fire_timer(&mut state, event_loop, tok, &mut target);
+            let live = state.timeouts.get(tok).map_or(false, |rec| rec.serial == serial);
+            if !live {
+                // cancelled from within its own handler
+                return;
+            }
+            if !present {
+                debug!("Cancelling interval {:?}, its context has gone away", tok);
+                state.remove_timer(tok);
+                return;
+            }
+        }
+
+        {
+            let rec = &mut state.timeouts[tok];
+            rec.target = Some(target);
+            if rec.timeout.is_some() {
+                // rescheduled from within its own handler, which has already set the deadline
+                return;
+            }
+            iv.deadline = match iv.schedule {
+                Schedule::FixedRate(_) => iv.deadline + (behind + 1) * iv.period,
+                Schedule::FixedDelay => now_ms() + iv.period
+            };
+            rec.interval = Some(iv);
+        }
+
+        match event_loop.timeout_ms(tok.0, iv.deadline.saturating_sub(now_ms())) {
+            Ok(t) => { state.timeouts[tok].timeout = Some(t); },
+            Err(e) => {
+                error!("Failed to re-arm interval {:?}: {:?}", tok, e);
+                state.remove_timer(tok);
+            }
+        }
+    }
+
+}
+
+/// Deliver a fired timer to its target. Returns false if the
+/// Context the timer was aimed at no longer exists
+fn fire_timer<'a>(state: &mut ReactorState<'a>,
+                  event_loop: &mut EventLoop<ReactorHandler<'a>>,
+                  tok: Token,
+                  target: &mut TimerTarget<'a>) -> bool {
+    match *target {
+        TimerTarget::Conn(ctxtok, tag) => {
+            match state.conns.replace(ctxtok, ConnRec::None) {
+                Some(ConnRec::Connected(mut ctx)) => {
+                    deliver(state, event_loop, ctxtok, &mut ctx, EventType::Timeout(tag));
+                    restore(state, event_loop, ctxtok, ctx, true);
+                    true
+                },
+                Some(ConnRec::None) => {
+                    warn!("Got a timeout event for {:?} while it was being dispatched, dropping it", ctxtok);
+                    true
+                },
+                Some(rec) => {
+                    warn!("Got a timeout event for pending connection {:?}, dropping it", ctxtok);
+                    state.conns.replace(ctxtok, rec);
+                    true
+                },
+                None => {
+                    state.stats.dropped_messages += 1;
+                    false
+                }
+            }
+        },
+        TimerTarget::Connect(ctxtok) => {
+            let conn = match state.conns.replace(ctxtok, ConnRec::None) {
+                Some(ConnRec::Pending(conn)) | Some(ConnRec::Resolving(conn)) => Some(conn),
+                Some(rec) => {
+                    // already connected, the timer should have been cancelled
+                    state.conns.replace(ctxtok, rec);
+                    None
+                },
+                None => None
+            };
+            if let Some(mut conn) = conn {
+                conn.error = Some(Error::new(ErrorKind::TimedOut, "Timed out establishing connection"));
+                state.fail_connect(event_loop, ctxtok, conn);
+            }
+            true
+        },
+        TimerTarget::Stagger(ctxtok) => {
+            state.next_attempt(event_loop, ctxtok);
+            true
+        },
+        TimerTarget::Reconnect(ctxtok) => {
+            state.redial(event_loop, ctxtok);
+            true
+        },
+        TimerTarget::Accept(ltok) => {
+            // unless it has since been paused or closed
+            let open = match state.listeners.get(ltok) {
+                Some(&Some(_)) => !state.paused_listeners.contains(&ltok),
+                _ => false
+            };
+            if open {
+                accept(state, event_loop, ltok);
+            }
+            true
+        },
+        TimerTarget::Handler(ref mut handler) => {
+            handler(tok, &mut ReactorCtrl::new(state, event_loop));
+            true
         }
     }
+}
 
+/// Deliver a single event to a Context which has been taken out of the conns slab.
+/// Returns false if the Context asked to be closed while handling it, in which case
+/// no further events should be delivered to it.
+fn deliver<'a>(state: &mut ReactorState<'a>,
+               event_loop: &mut EventLoop<ReactorHandler<'a>>,
+               token: Token,
+               ctx: &mut Box<Context>,
+               evt: EventType) -> bool {
+    state.current = Some(token);
+    ctx.on_event(&mut ReactorCtrl::new(state, event_loop), evt);
+    state.current = None;
+    !state.closing
 }
 
+/// Put a Context back into the conns slab after dispatch, reregistering its
+/// interest if it was changed, or it is registered oneshot, unless the socket has
+/// hung up (live is false). If it called `close_current` it is instead
+/// deregistered, its slot released and the Context dropped.
+fn restore<'a>(state: &mut ReactorState<'a>,
+               event_loop: &mut EventLoop<ReactorHandler<'a>>,
+               token: Token,
+               ctx: Box<Context>,
+               live: bool) {
+    let dirty = state.interest_dirty;
+    state.interest_dirty = false;
+
+    if state.closing {
+        state.closing = false;
+        if let Err(e) = event_loop.deregister(ctx.get_evented()) {
+            debug!("Failed to deregister closed context {:?}: {}", token, e);
+        }
+        state.conns.remove(token);
+        state.forget(event_loop, token);
+        debug!("Closed context for {:?}", token);
+        return;
+    }
+    // a Context may still change its interest by returning something new from
+    // get_interest, as it did before set_interest, which marks it dirty by then
+    let mut changed = dirty;
+    if live {
+        let reported = ctx.get_interest();
+        if state.reported_interests.insert(token, reported) != Some(reported) && !dirty {
+            state.interests.insert(token, reported);
+            changed = true;
+        }
+    }
+    // a oneshot registration is disabled by the event which was just delivered
+    let opts = ctx.get_poll_opt();
+    if live && (changed || opts.is_oneshot()) {
+        let interest = state.interests.get(&token).cloned().unwrap_or_else(|| ctx.get_interest());
+        if let Err(e) = event_loop.reregister(ctx.get_evented(), token,
+                interest | EventSet::hup(), opts) {
+            // the context can no longer receive events, drop it rather than leave it stranded
+            state.conns.remove(token);
+            state.forget(event_loop, token);
+            state.report_error(Some(token), e);
+            return;
+        }
+        state.stats.reregisters += 1;
+    }
+    else if live {
+        state.stats.reregisters_saved += 1;
+    }
+    state.conns.replace(token, ConnRec::Connected(ctx));
+}
@@ -0,0 +1,141 @@
+use std::io::{Error, ErrorKind, Result};
+use std::net::{SocketAddr, IpAddr, ToSocketAddrs};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::sync::mpsc;
+use std::thread;
+
+use mio::{Sender, Token, NotifyError};
+
+use reactor_ctrl::ReactorMsg;
+
+/// Identifies one of the resolvers owned by a Reactor, as returned by `add_resolver`
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ResolverId(pub usize);
+
+/// Every Reactor starts out owning a `SystemResolver` under this id
+pub const SYSTEM_RESOLVER: ResolverId = ResolverId(0);
+
+/// Resolutions which could not be sent because the event loop's channel was full,
+/// picked up by the event loop at the end of its tick
+pub(crate) type LateResolutions = Arc<Mutex<Vec<(Token, Result<Vec<SocketAddr>>)>>>;
+
+/// The completion for a single lookup, handed to `Resolver::resolve`.
+/// It may be sent to another thread, and the result is delivered back
+/// through the event loop to the connection which asked for it.
+pub struct Resolution {
+    token: Token,
+    sender: Sender<ReactorMsg>,
+    late: LateResolutions
+}
+
+impl Resolution {
+
+    pub(crate) fn new(token: Token, sender: Sender<ReactorMsg>, late: LateResolutions) -> Resolution {
+        Resolution { token: token, sender: sender, late: late }
+    }
+
+    /// Deliver the addresses for the lookup, or the reason there are none
+    pub fn complete(self, result: Result<Vec<SocketAddr>>) {
+        match self.sender.send(ReactorMsg::Resolved(self.token, result)) {
+            Ok(_) => {},
+            // a full channel means the event loop has messages waiting and will wake for them,
+            // the connection is then resolved at the end of that tick rather than left hanging
+            Err(NotifyError::Full(ReactorMsg::Resolved(token, result))) => {
+                self.late.lock().unwrap().push((token, result));
+            },
+            Err(NotifyError::Io(e)) => {
+                self.late.lock().unwrap().push((self.token, Err(e)));
+            },
+            Err(_) => {
+                debug!("Dropping resolution for {:?}, the event loop is gone", self.token);
+            }
+        }
+    }
+}
+
+/// Turns host names into addresses for `ReactorCtrl::connect` without blocking the
+/// event loop. An implementation must call `Resolution::complete` exactly once for
+/// each call to `resolve`, either before returning or later from any thread.
+pub trait Resolver {
+    fn resolve(&mut self, host: &str, port: u16, done: Resolution);
+}
+
+type Job = (String, u16, Resolution);
+
+/// Resolves names with the operating system's resolver (getaddrinfo), which blocks,
+/// so lookups are run on a small pool of worker threads. The threads are started
+/// on the first lookup and exit when the resolver is dropped.
+pub struct SystemResolver {
+    threads: usize,
+    jobs: Option<mpsc::Sender<Job>>
+}
+
+impl SystemResolver {
+
+    pub fn new(threads: usize) -> SystemResolver {
+        SystemResolver {
+            threads: if threads == 0 { 1 } else { threads },
+            jobs: None
+        }
+    }
+
+    fn start(&mut self) -> &mpsc::Sender<Job> {
+        if self.jobs.is_none() {
+            let (tx, rx) = mpsc::channel::<Job>();
+            let rx = Arc::new(Mutex::new(rx));
+            for _ in 0 .. self.threads {
+                let rx = rx.clone();
+                thread::spawn(move || {
+                    loop {
+                        let job = match rx.lock().unwrap().recv() {
+                            Ok(job) => job,
+                            Err(_) => return
+                        };
+                        let (host, port, done) = job;
+                        done.complete((&host[..], port).to_socket_addrs().map(|addrs| addrs.collect()));
+                    }
+                });
+            }
+            self.jobs = Some(tx);
+        }
+        self.jobs.as_ref().unwrap()
+    }
+}
+
+impl Resolver for SystemResolver {
+    fn resolve(&mut self, host: &str, port: u16, done: Resolution) {
+        if let Err(mpsc::SendError((_, _, done))) = self.start().send((host.to_owned(), port, done)) {
+            done.complete(Err(Error::new(ErrorKind::Other, "Resolver threads have exited")));
+        }
+    }
+}
+
+/// Resolves names from a fixed table, useful for tests and for pinning hosts
+/// without touching DNS
+pub struct StaticResolver {
+    hosts: HashMap<String, Vec<IpAddr>>
+}
+
+impl StaticResolver {
+
+    pub fn new() -> StaticResolver {
+        StaticResolver { hosts: HashMap::new() }
+    }
+
+    /// Add an address for host, a host may have several, which are returned in
+    /// the order they were added
+    pub fn add(&mut self, host: &str, addr: IpAddr) -> &mut StaticResolver {
+        self.hosts.entry(host.to_owned()).or_insert_with(Vec::new).push(addr);
+        self
+    }
+}
+
+impl Resolver for StaticResolver {
+    fn resolve(&mut self, host: &str, port: u16, done: Resolution) {
+        match self.hosts.get(host) {
+            Some(addrs) => done.complete(Ok(addrs.iter().map(|ip| SocketAddr::new(*ip, port)).collect())),
+            None => done.complete(Err(Error::new(ErrorKind::NotFound, format!("No address known for {}", host))))
+        }
+    }
+}
@@ -0,0 +1,131 @@
+use std::io::{self, Error, ErrorKind};
+use std::marker::PhantomData;
+
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+use tendril::{Tendril, Atomic};
+use tendril::fmt::Bytes;
+
+#[cfg(any(feature = "bincode-codec", feature = "msgpack-codec"))]
+use codec::{LengthPrefixedCodec, Width, Endian};
+#[cfg(feature = "json-codec")]
+use codec::LineCodec;
+use codec::Codec;
+use utils::ReadBuf;
+
+/// Values as JSON documents, one per line, with the `json-codec` feature.
+///
+/// A value which cannot be decoded has its line consumed before the error is
+/// handed to `FramedHandler::on_decode_error`, so a handler which keeps the
+/// connection open carries on with the next value. The same goes for the other
+/// serde codecs and their frames.
+#[cfg(feature = "json-codec")]
+pub struct JsonCodec<In, Out> {
+    lines : LineCodec,
+    _msgs : PhantomData<fn(Out) -> In>
+}
+
+#[cfg(feature = "json-codec")]
+impl<In, Out> JsonCodec<In, Out> {
+    pub fn new() -> JsonCodec<In, Out> {
+        JsonCodec {
+            lines: LineCodec::new(),
+            _msgs: PhantomData
+        }
+    }
+}
+
+#[cfg(feature = "json-codec")]
+impl<In : DeserializeOwned, Out : Serialize> Codec for JsonCodec<In, Out> {
+    type In = In;
+    type Out = Out;
+
+    fn decode(&mut self, buf : &mut ReadBuf) -> io::Result<Option<In>> {
+        match try!(self.lines.decode(buf)) {
+            Some(line) => ::serde_json::from_slice(&line).map(Some).map_err(invalid),
+            None => Ok(None)
+        }
+    }
+
+    fn encode(&mut self, msg : Out) -> io::Result<Tendril<Bytes, Atomic>> {
+        // compact JSON never holds a raw newline, so it cannot split a line
+        let json = try!(::serde_json::to_vec(&msg).map_err(invalid));
+        self.lines.encode(Tendril::from_slice(&json[..]))
+    }
+}
+
+/// Values encoded with bincode, each preceded by its length as a big endian u32,
+/// with the `bincode-codec` feature
+#[cfg(feature = "bincode-codec")]
+pub struct BincodeCodec<In, Out> {
+    frames : LengthPrefixedCodec,
+    _msgs : PhantomData<fn(Out) -> In>
+}
+
+#[cfg(feature = "bincode-codec")]
+impl<In, Out> BincodeCodec<In, Out> {
+    pub fn new() -> BincodeCodec<In, Out> {
+        BincodeCodec {
+            frames: LengthPrefixedCodec::new(Width::U32, Endian::Big),
+            _msgs: PhantomData
+        }
+    }
+}
+
+#[cfg(feature = "bincode-codec")]
+impl<In : DeserializeOwned, Out : Serialize> Codec for BincodeCodec<In, Out> {
+    type In = In;
+    type Out = Out;
+
+    fn decode(&mut self, buf : &mut ReadBuf) -> io::Result<Option<In>> {
+        match try!(self.frames.decode(buf)) {
+            Some(frame) => ::bincode::deserialize(&frame).map(Some).map_err(invalid),
+            None => Ok(None)
+        }
+    }
+
+    fn encode(&mut self, msg : Out) -> io::Result<Tendril<Bytes, Atomic>> {
+        let bytes = try!(::bincode::serialize(&msg).map_err(invalid));
+        self.frames.encode(Tendril::from_slice(&bytes[..]))
+    }
+}
+
+/// Values encoded as MessagePack, each preceded by its length as a big endian u32,
+/// with the `msgpack-codec` feature
+#[cfg(feature = "msgpack-codec")]
+pub struct MsgPackCodec<In, Out> {
+    frames : LengthPrefixedCodec,
+    _msgs : PhantomData<fn(Out) -> In>
+}
+
+#[cfg(feature = "msgpack-codec")]
+impl<In, Out> MsgPackCodec<In, Out> {
+    pub fn new() -> MsgPackCodec<In, Out> {
+        MsgPackCodec {
+            frames: LengthPrefixedCodec::new(Width::U32, Endian::Big),
+            _msgs: PhantomData
+        }
+    }
+}
+
+#[cfg(feature = "msgpack-codec")]
+impl<In : DeserializeOwned, Out : Serialize> Codec for MsgPackCodec<In, Out> {
+    type In = In;
+    type Out = Out;
+
+    fn decode(&mut self, buf : &mut ReadBuf) -> io::Result<Option<In>> {
+        match try!(self.frames.decode(buf)) {
+            Some(frame) => ::rmp_serde::from_slice(&frame).map(Some).map_err(invalid),
+            None => Ok(None)
+        }
+    }
+
+    fn encode(&mut self, msg : Out) -> io::Result<Tendril<Bytes, Atomic>> {
+        let bytes = try!(::rmp_serde::to_vec(&msg).map_err(invalid));
+        self.frames.encode(Tendril::from_slice(&bytes[..]))
+    }
+}
+
+fn invalid<E : ::std::error::Error + Send + Sync + 'static>(err : E) -> Error {
+    Error::new(ErrorKind::InvalidData, err)
+}
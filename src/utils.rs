@@ -1,73 +1,734 @@
-use std::io::Write;
+use std::io::{self, Read, Write, ErrorKind, IoSlice};
+use std::cmp;
 use std::collections::VecDeque;
+#[cfg(unix)]
+use std::mem::ManuallyDrop;
+#[cfg(unix)]
+use std::net;
+#[cfg(unix)]
+use std::os::unix::io::{AsRawFd, FromRawFd};
+#[cfg(unix)]
+use mio::tcp::TcpStream;
 use tendril::{Tendril, Atomic};
 use tendril::fmt::Bytes;
 
-/// Simple manager of outbound data for a non-blocking socket
+/// The most buffers handed to a single vectored write, the usual limit on an iovec
+pub const IOV_MAX: usize = 1024;
+
+/// Bytes asked of the socket by each read of a `ReadBuf`
+const READ_CHUNK: usize = 16 * 1024;
+
+/// What a Context should do after handing data to an `OutQueue`
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Flow {
+    /// Everything has been written, writable interest is no longer needed
+    Done,
+    /// Some data is still queued, keep writable interest and `drain` when writable
+    Wait,
+    /// The queue has grown past its high water mark, stop producing data for it
+    Pause,
+    /// The queue has shrunk to its low water mark after a `Pause`, start producing
+    /// again. Data may still be queued, check `is_empty` before dropping writable interest
+    Resume
+}
+
+/// Simple manager of outbound data for a non-blocking socket.
+///
+/// Data which cannot be written straight away is queued until the socket is
+/// writable again. So that a slow peer cannot make us buffer without limit, once
+/// more than the high water mark is queued the queue answers `Flow::Pause`, and once
+/// it has drained down to the low water mark, `Flow::Resume`.
+///
+///```
+/// extern crate reactor;
+/// extern crate tendril;
+///
+/// use std::io::{self, Write, ErrorKind};
+/// use tendril::{Tendril, Atomic};
+/// use tendril::fmt::Bytes;
+/// use reactor::utils::{OutQueue, Flow};
+///
+/// // a socket which takes `room` more bytes before it would block
+/// struct Slow { room: usize, out: Vec<u8> }
+///
+/// impl Write for Slow {
+///     fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+///         if self.room == 0 {
+///             return Err(io::Error::new(ErrorKind::WouldBlock, "full"));
+///         }
+///         let n = std::cmp::min(self.room, buf.len());
+///         self.room -= n;
+///         self.out.extend(buf[..n].iter().cloned());
+///         Ok(n)
+///     }
+///     fn flush(&mut self) -> io::Result<()> { Ok(()) }
+/// }
+///
+/// fn bytes(s: &str) -> Tendril<Bytes, Atomic> {
+///     Tendril::from_slice(s.as_bytes())
+/// }
+///
+/// fn main() {
+///     let mut sock = Slow { room: 3, out: Vec::new() };
+///     let mut q = OutQueue::with_water_marks(2, 6);
+///
+///     assert_eq!(q.write(bytes("ab"), &mut sock).unwrap(), Flow::Done);
+///     assert_eq!(q.write(bytes("cdef"), &mut sock).unwrap(), Flow::Wait);
+///     assert_eq!(q.len(), 3);
+///     assert_eq!(q.write(bytes("ghij"), &mut sock).unwrap(), Flow::Pause);
+///
+///     sock.room = 5;
+///     assert_eq!(q.drain(&mut sock).unwrap(), Flow::Resume);
+///     assert_eq!(q.len(), 2);
+///     sock.room = 10;
+///     assert_eq!(q.drain(&mut sock).unwrap(), Flow::Done);
+///     assert_eq!(&sock.out[..], b"abcdefghij");
+/// }
+///```
 pub struct OutQueue {
     q : VecDeque<Tendril<Bytes, Atomic>>,
-    offset : usize
+    /// bytes of the front buffer which have already been written
+    offset : usize,
+    /// bytes queued and not yet written
+    len : usize,
+    low_water : usize,
+    high_water : usize,
+    paused : bool,
+    vectored : bool
 }
 
 impl OutQueue {
 
+    /// An empty queue which pauses above 1MiB queued and resumes at 256KiB
+    pub fn new() -> OutQueue {
+        OutQueue::with_water_marks(256 * 1024, 1024 * 1024)
+    }
+
+    /// An empty queue which pauses once more than high bytes are queued, and
+    /// resumes once no more than low are
+    ///
+    /// Panics if low is greater than high
+    pub fn with_water_marks(low : usize, high : usize) -> OutQueue {
+        assert!(low <= high, "OutQueue low water mark is above its high water mark");
+        OutQueue {
+            q: VecDeque::new(),
+            offset: 0,
+            len: 0,
+            low_water: low,
+            high_water: high,
+            paused: false,
+            vectored: false
+        }
+    }
+
+    /// Whether `drain` gathers queued buffers into one vectored write, or writes them
+    /// one at a time, which is the default. Only turn this on for a writer which
+    /// overrides `write_vectored`, mio's sockets do not, wrap them in `Writev`
+    pub fn set_vectored(&mut self, vectored : bool) {
+        self.vectored = vectored;
+    }
+
+    /// Bytes queued and not yet written
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.q.is_empty()
+    }
+
+    /// Whether the last `Flow` given out was `Pause`, rather than `Resume`
+    pub fn is_paused(&self) -> bool {
+        self.paused
+    }
+
     /// Attempt to write data into non-blocking socket.
-    /// If all data was successfully written, then return true,
-    /// otherwise place remaining data in queue to be written at next
-    /// writable event.  User of this function should set their interest
-    /// to writable upon a false result of this function
-    pub fn write<W : Write>(&mut self, buf : Tendril<Bytes, Atomic>, sock : &mut W) -> bool {
-        let b = buf;
+    /// Whatever cannot be written is queued, to be written by `drain` at the next
+    /// writable event, and the result says whether that is needed. User of this
+    /// function should set their interest to writable unless it is `Flow::Done`.
+    /// Errors other than WouldBlock are returned, and the data is dropped.
+    pub fn write<W : Write>(&mut self, buf : Tendril<Bytes, Atomic>, sock : &mut W) -> io::Result<Flow> {
+        if buf.len() == 0 {
+            return Ok(if self.q.is_empty() { Flow::Done } else { Flow::Wait });
+        }
+        let mut written = 0;
         if self.q.is_empty() {
-            if let Ok(n) = sock.write(&b[self.offset .. ]) {
-                self.offset += n;
-                if b.len() <= self.offset {
-                    return true;
+            while written < buf.len() {
+                match sock.write(&buf[written .. ]) {
+                    Ok(0) => return Err(io::Error::new(ErrorKind::WriteZero, "failed to write any bytes")),
+                    Ok(n) => written += n,
+                    Err(ref e) if e.kind() == ErrorKind::WouldBlock => break,
+                    Err(ref e) if e.kind() == ErrorKind::Interrupted => continue,
+                    Err(e) => return Err(e)
                 }
             }
+            if written == buf.len() {
+                return Ok(Flow::Done);
+            }
+            // the front buffer is partly written
+            self.offset = written;
+        }
+        self.len += buf.len() - written;
+        self.q.push_back(buf);
+
+        if !self.paused && self.len > self.high_water {
+            self.paused = true;
+            return Ok(Flow::Pause);
         }
-        self.q.push_back(b);
-        false
-    }
-
-    /// Attempt to empty the existing write queue into this non-blocking socket
-    /// If all data was successfully written, then return true,
-    /// otherwise place remaining data in queue to be written at next
-    /// writable event.  User of this function should set their interest
-    /// to writable upon a false result of this function
-    pub fn drain<W : Write>(&mut self, sock : &mut W) -> bool {
-        let mut writable = true;
-        while writable && !self.q.is_empty() {
-            let mut flushed = false;
-            {
-                let buf = self.q.front_mut().unwrap(); //shouldn't panic because of is_empty() check
-                let sz = buf.len();
+        Ok(Flow::Wait)
+    }
+
+    /// Attempt to empty the existing write queue into this non-blocking socket,
+    /// until it is empty or the socket would block. Once vectored writes are turned
+    /// on, up to `IOV_MAX` buffers are handed to each `write_vectored`, which is one
+    /// syscall for a socket wrapped in `Writev`.
+    /// User of this function should drop their writable interest once it
+    /// returns `Flow::Done`. It returns `Flow::Resume` instead the first time the
+    /// queue is down to its low water mark after a `Pause`, which may be with the
+    /// queue empty, so writable interest can be dropped then too if `is_empty`.
+    pub fn drain<W : Write>(&mut self, sock : &mut W) -> io::Result<Flow> {
+        while !self.q.is_empty() {
+            let (n, res) = if self.vectored && self.q.len() > 1 {
+                self.write_batch(sock)
+            }
+            else {
+                let buf = self.q.front().unwrap(); //shouldn't panic because of is_empty() check
                 match sock.write(&buf[self.offset .. ]) {
-                    Ok(n) =>
-                    {
-                        if n == 0 {
-                            error!("Got Writable event for socket, but failed to write any bytes");
-                            writable = false;
-                        }
-                        else if n == sz {
-                            flushed = true;
-                        } else {
-                            self.offset += n;
-                        }
-                    },
-                    Err(e) => { error!("error writing to socket: {:?}", e); writable = false }
+                    Ok(0) => (0, Err(io::Error::new(ErrorKind::WriteZero, "failed to write any bytes"))),
+                    Ok(n) => (n, Ok(())),
+                    Err(e) => (0, Err(e))
                 }
-            }
-            if flushed {
-                self.q.pop_front(); // we have written the contents of this buffer so lets get rid of it
+            };
+            self.consume(n);
+            match res {
+                Ok(_) => {},
+                Err(ref e) if e.kind() == ErrorKind::WouldBlock => break,
+                Err(ref e) if e.kind() == ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e)
             }
         }
 
+        if self.paused && self.len <= self.low_water {
+            self.paused = false;
+            return Ok(Flow::Resume);
+        }
         if self.q.is_empty() {
-            true
+            Ok(Flow::Done)
         }
         else {
-            false
+            Ok(Flow::Wait)
+        }
+    }
+
+    /// Write up to `IOV_MAX` buffers from the front of the queue, gathering as many
+    /// as the socket will take into each vectored write. Returns the bytes written,
+    /// which are yet to be consumed, and why the batch was cut short if it was.
+    fn write_batch<W : Write>(&self, sock : &mut W) -> (usize, io::Result<()>) {
+        let mut iov : Vec<IoSlice> = self.q.iter().take(IOV_MAX).enumerate()
+            .map(|(i, buf)| IoSlice::new(&buf[if i == 0 { self.offset } else { 0 } .. ]))
+            .collect();
+        // the queue index and offset of the first byte not yet written
+        let (mut first, mut offset) = (0, self.offset);
+        let mut written = 0;
+        while !iov.is_empty() {
+            match sock.write_vectored(&iov) {
+                Ok(0) => return (written, Err(io::Error::new(ErrorKind::WriteZero, "failed to write any bytes"))),
+                Ok(n) => {
+                    written += n;
+                    // drop the buffers written in full, and start the next one where
+                    // this write ended
+                    let start = first;
+                    let mut n = n;
+                    while n > 0 {
+                        let left = self.q[first].len() - offset;
+                        if n < left {
+                            offset += n;
+                            break;
+                        }
+                        n -= left;
+                        first += 1;
+                        offset = 0;
+                    }
+                    iov.drain(.. first - start);
+                    if !iov.is_empty() {
+                        iov[0] = IoSlice::new(&self.q[first][offset .. ]);
+                    }
+                },
+                Err(ref e) if e.kind() == ErrorKind::Interrupted => continue,
+                Err(e) => return (written, Err(e))
+            }
+        }
+        (written, Ok(()))
+    }
+
+    /// Account for n bytes of the queue having been written
+    fn consume(&mut self, mut n : usize) {
+        self.len -= n;
+        while n > 0 {
+            let left = self.q.front().unwrap().len() - self.offset;
+            if n < left {
+                self.offset += n;
+                return;
+            }
+            // we have written the contents of this buffer so lets get rid of it
+            n -= left;
+            self.q.pop_front();
+            self.offset = 0;
+        }
+    }
+}
+
+impl Default for OutQueue {
+    fn default() -> OutQueue {
+        OutQueue::new()
+    }
+}
+
+/// Gives a mio socket a `write_vectored` which issues a single writev on its file
+/// descriptor. mio's sockets leave `write_vectored` to the default, which writes
+/// only the first buffer, so an `OutQueue` with vectored writes turned on needs
+/// them wrapped in this to save any syscalls.
+///
+///```ignore
+/// queue.set_vectored(true);
+/// let flow = try!(queue.drain(&mut Writev(&mut sock)));
+///```
+#[cfg(unix)]
+pub struct Writev<'a>(pub &'a mut TcpStream);
+
+#[cfg(unix)]
+impl<'a> Write for Writev<'a> {
+    fn write(&mut self, buf : &[u8]) -> io::Result<usize> {
+        self.0.write(buf)
+    }
+
+    fn write_vectored(&mut self, bufs : &[IoSlice]) -> io::Result<usize> {
+        // the descriptor is a TCP socket, so std's TcpStream may borrow it to issue
+        // the writev. It is never dropped, the descriptor stays with the mio socket
+        let fd = ManuallyDrop::new(unsafe { net::TcpStream::from_raw_fd(self.0.as_raw_fd()) });
+        (&*fd as &net::TcpStream).write_vectored(bufs)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.0.flush()
+    }
+}
+
+/// Simple manager of inbound data from a non-blocking socket.
+///
+/// Everything the socket has is read into one growable buffer, so that a frame
+/// split across reads is never lost. Complete frames can then be looked at with
+/// `peek` and handed out with `take`, which shares the buffer rather than copying it.
+/// So that a peer cannot make us buffer without limit, reading past the maximum
+/// size is an error.
+///
+///```
+/// extern crate reactor;
+///
+/// use std::io::{self, Read, ErrorKind};
+/// use reactor::utils::ReadBuf;
+///
+/// // a socket which hands out its chunks one read at a time, then would block
+/// struct Chunks { chunks: Vec<&'static [u8]>, closed: bool }
+///
+/// impl Read for Chunks {
+///     fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+///         if self.chunks.is_empty() {
+///             if self.closed {
+///                 return Ok(0);
+///             }
+///             return Err(io::Error::new(ErrorKind::WouldBlock, "empty"));
+///         }
+///         let chunk = self.chunks.remove(0);
+///         let n = std::cmp::min(buf.len(), chunk.len());
+///         buf[..n].copy_from_slice(&chunk[..n]);
+///         if n < chunk.len() {
+///             self.chunks.insert(0, &chunk[n..]);
+///         }
+///         Ok(n)
+///     }
+/// }
+///
+/// fn main() {
+///     let mut sock = Chunks { chunks: vec![&b"hel"[..], &b"lo\nwor"[..]], closed: false };
+///     let mut buf = ReadBuf::with_max_size(16);
+///
+///     assert_eq!(buf.read_from(&mut sock).unwrap(), 9);
+///     assert_eq!(buf.peek(), &b"hello\nwor"[..]);
+///     let line = buf.take(6);
+///     assert_eq!(&line[..], &b"hello\n"[..]);
+///     assert_eq!(buf.peek(), &b"wor"[..]);
+///
+///     sock.chunks.push(&b"ld\n"[..]);
+///     sock.closed = true;
+///     assert_eq!(buf.read_from(&mut sock).unwrap(), 3);
+///     assert!(buf.is_eof());
+///     buf.consume(6);
+///     assert!(buf.is_empty());
+///
+///     let mut flood = Chunks { chunks: vec![&b"0123456789"[..], &b"0123456789"[..]], closed: false };
+///     let mut small = ReadBuf::with_max_size(16);
+///     assert!(small.read_from(&mut flood).is_err());
+/// }
+///```
+pub struct ReadBuf {
+    buf : Tendril<Bytes, Atomic>,
+    max_size : usize,
+    eof : bool,
+    /// more than max_size was read, which is not recovered from
+    overflowed : bool
+}
+
+impl ReadBuf {
+
+    /// An empty buffer which holds up to 1MiB
+    pub fn new() -> ReadBuf {
+        ReadBuf::with_max_size(1024 * 1024)
+    }
+
+    /// An empty buffer which holds up to max bytes
+    ///
+    /// Panics unless max is less than `u32::MAX`, since the buffer may be read one
+    /// byte past max to tell it is overflowing, and a Tendril holds `u32::MAX` bytes
+    pub fn with_max_size(max : usize) -> ReadBuf {
+        assert!((max as u64) < u32::max_value() as u64, "ReadBuf max size is not under u32::MAX");
+        ReadBuf {
+            buf: Tendril::new(),
+            max_size: max,
+            eof: false,
+            overflowed: false
+        }
+    }
+
+    /// Bytes read and not yet consumed
+    pub fn len(&self) -> usize {
+        self.buf.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.buf.len() == 0
+    }
+
+    /// Whether the peer has closed its side, so no more data will be read
+    pub fn is_eof(&self) -> bool {
+        self.eof
+    }
+
+    /// Read from this non-blocking socket until it would block or is closed,
+    /// returning the number of bytes read. Once more than the maximum size is
+    /// buffered an error is returned, as it is by every read after, and the
+    /// connection should be closed.
+    pub fn read_from<R : Read>(&mut self, sock : &mut R) -> io::Result<usize> {
+        if self.overflowed {
+            return Err(io::Error::new(ErrorKind::Other, "read buffer overflow"));
+        }
+        let mut chunk = [0u8; READ_CHUNK];
+        let mut total = 0;
+        while !self.eof {
+            // ask for one byte more than there is room for, to tell a full buffer
+            // from an overflowing one
+            let want = cmp::min(READ_CHUNK, self.max_size - self.buf.len() + 1);
+            match sock.read(&mut chunk[.. want]) {
+                Ok(0) => self.eof = true,
+                Ok(n) => {
+                    self.buf.push_slice(&chunk[.. n]);
+                    total += n;
+                    if self.buf.len() > self.max_size {
+                        self.overflowed = true;
+                        return Err(io::Error::new(ErrorKind::Other, "read buffer overflow"));
+                    }
+                },
+                Err(ref e) if e.kind() == ErrorKind::WouldBlock => break,
+                Err(ref e) if e.kind() == ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e)
+            }
+        }
+        Ok(total)
+    }
+
+    /// The bytes read and not yet consumed
+    pub fn peek(&self) -> &[u8] {
+        &self.buf
+    }
+
+    /// Discard the first n bytes
+    ///
+    /// Panics if fewer than n bytes are buffered
+    pub fn consume(&mut self, n : usize) {
+        assert!(n <= self.buf.len(), "consumed past the end of a ReadBuf");
+        self.buf.pop_front(n as u32);
+    }
+
+    /// Hand out the first n bytes, sharing rather than copying them
+    ///
+    /// Panics if fewer than n bytes are buffered
+    pub fn take(&mut self, n : usize) -> Tendril<Bytes, Atomic> {
+        assert!(n <= self.buf.len(), "took past the end of a ReadBuf");
+        let frame = self.buf.subtendril(0, n as u32);
+        self.buf.pop_front(n as u32);
+        frame
+    }
+}
+
+impl Default for ReadBuf {
+    fn default() -> ReadBuf {
+        ReadBuf::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cmp;
+    use std::io::{self, Read, Write, ErrorKind, IoSlice};
+    use tendril::{Tendril, Atomic};
+    use tendril::fmt::Bytes;
+    use super::{OutQueue, ReadBuf, Flow};
+
+    /// A socket which takes room more bytes before it would block, across as many
+    /// buffers of a vectored write as they fill
+    struct Sock { room : usize, out : Vec<u8>, writes : usize }
+
+    impl Sock {
+        fn new(room : usize) -> Sock {
+            Sock { room: room, out: Vec::new(), writes: 0 }
+        }
+    }
+
+    impl Write for Sock {
+        fn write(&mut self, buf : &[u8]) -> io::Result<usize> {
+            self.write_vectored(&[IoSlice::new(buf)])
+        }
+
+        fn write_vectored(&mut self, bufs : &[IoSlice]) -> io::Result<usize> {
+            if self.room == 0 {
+                return Err(io::Error::new(ErrorKind::WouldBlock, "full"));
+            }
+            self.writes += 1;
+            let mut n = 0;
+            for buf in bufs {
+                let take = cmp::min(self.room - n, buf.len());
+                self.out.extend_from_slice(&buf[.. take]);
+                n += take;
+            }
+            self.room -= n;
+            Ok(n)
+        }
+
+        fn flush(&mut self) -> io::Result<()> { Ok(()) }
+    }
+
+    fn bytes(s : &str) -> Tendril<Bytes, Atomic> {
+        Tendril::from_slice(s.as_bytes())
+    }
+
+    #[test]
+    fn partial_write_resumes_mid_buffer() {
+        let mut sock = Sock::new(2);
+        let mut q = OutQueue::new();
+        assert_eq!(q.write(bytes("abcdef"), &mut sock).unwrap(), Flow::Wait);
+        assert_eq!(q.len(), 4);
+
+        sock.room = 3;
+        assert_eq!(q.drain(&mut sock).unwrap(), Flow::Wait);
+        assert_eq!(q.len(), 1);
+
+        sock.room = 10;
+        assert_eq!(q.drain(&mut sock).unwrap(), Flow::Done);
+        assert!(q.is_empty());
+        assert_eq!(&sock.out[..], b"abcdef");
+    }
+
+    #[test]
+    fn vectored_write_ends_inside_a_later_buffer() {
+        let mut sock = Sock::new(0);
+        let mut q = OutQueue::new();
+        q.set_vectored(true);
+        for s in &["ab", "cd", "ef"] {
+            assert_eq!(q.write(bytes(s), &mut sock).unwrap(), Flow::Wait);
+        }
+
+        sock.room = 3;
+        assert_eq!(q.drain(&mut sock).unwrap(), Flow::Wait);
+        assert_eq!(sock.writes, 1);
+        assert_eq!(q.len(), 3);
+
+        sock.room = 10;
+        assert_eq!(q.drain(&mut sock).unwrap(), Flow::Done);
+        assert_eq!(sock.writes, 2);
+        assert_eq!(&sock.out[..], b"abcdef");
+    }
+
+    /// Takes at most 3 bytes a call, so a vectored write is cut short again and again
+    struct Trickle { out : Vec<u8>, writes : usize }
+
+    impl Write for Trickle {
+        fn write(&mut self, buf : &[u8]) -> io::Result<usize> {
+            self.write_vectored(&[IoSlice::new(buf)])
+        }
+
+        fn write_vectored(&mut self, bufs : &[IoSlice]) -> io::Result<usize> {
+            self.writes += 1;
+            let mut n = 0;
+            for buf in bufs {
+                let take = cmp::min(3 - n, buf.len());
+                self.out.extend_from_slice(&buf[.. take]);
+                n += take;
+            }
+            Ok(n)
+        }
+
+        fn flush(&mut self) -> io::Result<()> { Ok(()) }
+    }
+
+    #[test]
+    fn vectored_drain_picks_up_where_each_write_ended() {
+        let mut sock = Sock::new(0);
+        let mut q = OutQueue::new();
+        q.set_vectored(true);
+        for s in &["abcd", "e", "fg", "hijklm", "n"] {
+            q.write(bytes(s), &mut sock).unwrap();
+        }
+
+        let mut sock = Trickle { out: Vec::new(), writes: 0 };
+        assert_eq!(q.drain(&mut sock).unwrap(), Flow::Done);
+        assert_eq!(&sock.out[..], b"abcdefghijklmn");
+        assert_eq!(sock.writes, 5);
+    }
+
+    #[test]
+    fn drain_writes_a_buffer_at_a_time_by_default() {
+        let mut sock = Sock::new(0);
+        let mut q = OutQueue::new();
+        for s in &["ab", "cd", "ef"] {
+            q.write(bytes(s), &mut sock).unwrap();
+        }
+
+        sock.room = 10;
+        assert_eq!(q.drain(&mut sock).unwrap(), Flow::Done);
+        assert_eq!(sock.writes, 3);
+        assert_eq!(&sock.out[..], b"abcdef");
+    }
+
+    #[test]
+    fn resume_may_leave_the_queue_empty() {
+        let mut sock = Sock::new(0);
+        let mut q = OutQueue::with_water_marks(2, 4);
+        assert_eq!(q.write(bytes("abc"), &mut sock).unwrap(), Flow::Wait);
+        assert_eq!(q.write(bytes("de"), &mut sock).unwrap(), Flow::Pause);
+        assert!(q.is_paused());
+        // already paused, so no second Pause
+        assert_eq!(q.write(bytes("f"), &mut sock).unwrap(), Flow::Wait);
+
+        sock.room = 10;
+        assert_eq!(q.drain(&mut sock).unwrap(), Flow::Resume);
+        assert!(q.is_empty());
+        assert!(!q.is_paused());
+        assert_eq!(q.drain(&mut sock).unwrap(), Flow::Done);
+    }
+
+    #[test]
+    fn empty_write_reports_the_queue() {
+        let mut sock = Sock::new(0);
+        let mut q = OutQueue::new();
+        assert_eq!(q.write(bytes(""), &mut sock).unwrap(), Flow::Done);
+        q.write(bytes("a"), &mut sock).unwrap();
+        assert_eq!(q.write(bytes(""), &mut sock).unwrap(), Flow::Wait);
+        assert_eq!(q.len(), 1);
+    }
+
+    #[test]
+    #[should_panic]
+    fn water_marks_must_be_ordered() {
+        OutQueue::with_water_marks(5, 4);
+    }
+
+    /// A socket which hands out at most `per_read` bytes of its data at a time
+    struct Source { data : Vec<u8>, per_read : usize, closed : bool }
+
+    impl Read for Source {
+        fn read(&mut self, buf : &mut [u8]) -> io::Result<usize> {
+            if self.data.is_empty() {
+                if self.closed {
+                    return Ok(0);
+                }
+                return Err(io::Error::new(ErrorKind::WouldBlock, "empty"));
+            }
+            let n = cmp::min(cmp::min(buf.len(), self.per_read), self.data.len());
+            buf[.. n].copy_from_slice(&self.data[.. n]);
+            self.data.drain(.. n);
+            Ok(n)
         }
     }
+
+    fn source(data : &[u8], per_read : usize) -> Source {
+        Source { data: data.to_vec(), per_read: per_read, closed: false }
+    }
+
+    #[test]
+    fn short_reads_are_gathered() {
+        let mut sock = source(b"hello world", 3);
+        let mut buf = ReadBuf::with_max_size(64);
+        assert_eq!(buf.read_from(&mut sock).unwrap(), 11);
+        assert_eq!(buf.peek(), &b"hello world"[..]);
+        assert!(!buf.is_eof());
+
+        sock.closed = true;
+        assert_eq!(buf.read_from(&mut sock).unwrap(), 0);
+        assert!(buf.is_eof());
+        assert_eq!(buf.len(), 11);
+    }
+
+    #[test]
+    fn take_and_peek_share_the_buffer() {
+        let mut sock = source(b"abcdef", 64);
+        let mut buf = ReadBuf::with_max_size(64);
+        buf.read_from(&mut sock).unwrap();
+
+        let front = buf.take(2);
+        assert_eq!(&front[..], &b"ab"[..]);
+        assert_eq!(buf.peek(), &b"cdef"[..]);
+        buf.consume(3);
+        assert_eq!(buf.peek(), &b"f"[..]);
+        assert_eq!(&buf.take(1)[..], &b"f"[..]);
+        assert!(buf.is_empty());
+    }
+
+    #[test]
+    fn full_is_not_overflow() {
+        let mut sock = source(b"12345678", 64);
+        let mut buf = ReadBuf::with_max_size(8);
+        assert_eq!(buf.read_from(&mut sock).unwrap(), 8);
+        assert_eq!(buf.len(), 8);
+    }
+
+    #[test]
+    fn overflow_is_sticky() {
+        let mut sock = source(b"0123456789", 4);
+        let mut buf = ReadBuf::with_max_size(8);
+        assert!(buf.read_from(&mut sock).is_err());
+        assert_eq!(buf.len(), 9);
+
+        // nothing more is read, even with room made
+        buf.consume(9);
+        assert!(buf.read_from(&mut sock).is_err());
+        assert_eq!(sock.data, b"9".to_vec());
+        assert!(!buf.is_eof());
+    }
+
+    #[test]
+    #[should_panic]
+    fn take_past_the_end() {
+        ReadBuf::with_max_size(8).take(1);
+    }
+
+    #[test]
+    fn max_size_leaves_room_to_overflow() {
+        ReadBuf::with_max_size(u32::max_value() as usize - 1);
+    }
+
+    #[test]
+    #[should_panic]
+    fn max_size_of_u32_max_is_too_big() {
+        ReadBuf::with_max_size(u32::max_value() as usize);
+    }
 }
@@ -0,0 +1,79 @@
+//! Helpers shared by the integration tests, not every test uses all of them.
+#![allow(dead_code)]
+
+use std::cell::RefCell;
+use std::net::{self, TcpListener};
+use std::rc::Rc;
+use reactor::{Reactor,
+              ReactorCtrl,
+              ReactorConfig,
+              Context,
+              EventType,
+              EventSet,
+              Evented};
+
+use reactor::tcp::TcpStream;
+
+/// What the Contexts of a test have heard, in order
+pub type Log = Rc<RefCell<Vec<String>>>;
+
+pub fn log() -> Log {
+    Rc::new(RefCell::new(Vec::new()))
+}
+
+/// Logs every event it is given: a notify as its text, a timeout as "timeout <tag>",
+/// and anything else by name
+pub struct Recorder {
+    pub sock : TcpStream,
+    pub log : Log,
+    pub interest : EventSet
+}
+
+impl Recorder {
+    /// A Recorder which is only interested in reading
+    pub fn new(sock : TcpStream, log : &Log) -> Recorder {
+        Recorder { sock: sock, log: log.clone(), interest: EventSet::readable() }
+    }
+}
+
+impl Context for Recorder {
+    fn on_event(&mut self, _ctrl : &mut ReactorCtrl, evt : EventType) {
+        let entry = match evt {
+            EventType::Readable => "Readable".to_string(),
+            EventType::Writable => "Writable".to_string(),
+            EventType::Disconnect => "Disconnect".to_string(),
+            EventType::Notify(buf) => String::from_utf8_lossy(&buf).into_owned(),
+            EventType::Timeout(tag) => format!("timeout {}", tag),
+            _ => "other".to_string()
+        };
+        self.log.borrow_mut().push(entry);
+    }
+
+    fn get_evented(&self) -> &Evented {
+        &self.sock as &Evented
+    }
+
+    fn get_interest(&self) -> EventSet {
+        self.interest
+    }
+}
+
+/// A connected socket for a Context to own, with its peer, which must be kept
+/// open for as long as the Context is wanted
+pub fn socket(listener : &TcpListener) -> (TcpStream, net::TcpStream) {
+    let sock = TcpStream::connect(&listener.local_addr().unwrap()).unwrap();
+    let (peer, _) = listener.accept().unwrap();
+    (sock, peer)
+}
+
+/// A reactor which fails the test rather than hang if it is still running after 5s
+pub fn guarded() -> Reactor<'static> {
+    guarded_with(ReactorConfig::default())
+}
+
+/// As `guarded`, configured with cfg
+pub fn guarded_with(cfg : ReactorConfig) -> Reactor<'static> {
+    let mut r = Reactor::configured(cfg);
+    r.timeout(5000, Box::new(|_, _| panic!("Test did not finish within 5s"))).unwrap();
+    r
+}
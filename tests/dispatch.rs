@@ -0,0 +1,44 @@
+//! A socket which is readable and writable at once must get both events in the same
+//! tick, `Readable` first, followed by `Disconnect` if the peer has hung up.
+
+extern crate reactor;
+
+mod common;
+
+use std::io::Write;
+use std::net::TcpListener;
+use reactor::EventSet;
+
+use common::{Recorder, socket, guarded};
+
+/// The events of the first tick in which the Context hears anything, for a socket
+/// whose peer has written to it and, if hang_up, closed its end
+fn first_tick(hang_up : bool) -> Vec<String> {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let (sock, mut peer) = socket(&listener);
+    peer.write_all(b"hello").unwrap();
+    if hang_up {
+        drop(peer);
+    }
+
+    let log = common::log();
+    let mut r = guarded();
+    let interest = EventSet::readable() | EventSet::writable() | EventSet::hup();
+    r.register(Recorder { sock: sock, log: log.clone(), interest: interest }).unwrap();
+
+    while log.borrow().is_empty() {
+        r.run_once();
+    }
+    let events = log.borrow().clone();
+    events
+}
+
+#[test]
+fn readable_and_writable_in_one_tick() {
+    assert_eq!(first_tick(false), vec!["Readable", "Writable"]);
+}
+
+#[test]
+fn disconnect_comes_last() {
+    assert_eq!(first_tick(true), vec!["Readable", "Writable", "Disconnect"]);
+}
@@ -0,0 +1,168 @@
+//! A `ConnPool` against a local echo server: connections are only lent out once their
+//! Context is registered, and one whose Context closes itself is replaced right away.
+
+extern crate reactor;
+extern crate tendril;
+
+mod common;
+
+use std::cell::{Cell, RefCell};
+use std::io::{Read, Write};
+use std::net::TcpListener;
+use std::rc::Rc;
+use std::thread;
+use tendril::Tendril;
+use reactor::{Reactor,
+              ReactorCtrl,
+              ConnPool,
+              PoolConfig,
+              Context,
+              EventType,
+              EventSet,
+              Evented,
+              PollOpt,
+              Token,
+              ResolverId,
+              SYSTEM_RESOLVER};
+
+use reactor::tcp::TcpStream;
+
+use common::guarded;
+
+/// Echo every connection made to a fresh local port, on threads of its own
+fn echo_server() -> u16 {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let port = listener.local_addr().unwrap().port();
+    thread::spawn(move || {
+        for sock in listener.incoming() {
+            let mut sock = match sock { Ok(s) => s, Err(_) => return };
+            thread::spawn(move || {
+                let mut buf = [0u8; 64];
+                loop {
+                    match sock.read(&mut buf) {
+                        Ok(0) | Err(_) => return,
+                        Ok(n) => if sock.write_all(&buf[..n]).is_err() { return }
+                    }
+                }
+            });
+        }
+    });
+    port
+}
+
+type Pool = Rc<RefCell<Option<ConnPool>>>;
+
+/// Writes each notify to the server, checks its connection back in once the echo
+/// arrives, and closes itself on being told to "quit"
+struct Client {
+    sock : TcpStream,
+    token : Token,
+    pool : Pool,
+    answered : Rc<Cell<usize>>
+}
+
+impl Context for Client {
+    fn on_event(&mut self, ctrl : &mut ReactorCtrl, evt : EventType) {
+        match evt {
+            EventType::Notify(ref buf) if &buf[..] == b"quit" => {
+                ctrl.close_current().unwrap();
+            },
+            EventType::Notify(buf) => {
+                self.sock.write_all(&buf).unwrap();
+            },
+            EventType::Readable => {
+                let mut buf = [0u8; 4];
+                if let Ok(4) = self.sock.read(&mut buf) {
+                    self.answered.set(self.answered.get() + 1);
+                    let pool = self.pool.borrow().clone().unwrap();
+                    pool.checkin(ctrl, self.token);
+                }
+            },
+            _ => {}
+        }
+    }
+
+    fn get_evented(&self) -> &Evented {
+        &self.sock as &Evented
+    }
+
+    fn get_interest(&self) -> EventSet {
+        EventSet::readable()
+    }
+
+    fn get_poll_opt(&self) -> PollOpt {
+        PollOpt::level()
+    }
+}
+
+/// A reactor with a pool of between min and max connections to a local echo server,
+/// whose factory counts the connections it has been asked for
+fn pool(min : usize, max : usize, answered : &Rc<Cell<usize>>, made : &Rc<Cell<usize>>) -> (Reactor<'static>, ConnPool) {
+    let port = echo_server();
+    let mut r = guarded();
+
+    let shared : Pool = Rc::new(RefCell::new(None));
+    let (s, a, m) = (shared.clone(), answered.clone(), made.clone());
+    let pool = ConnPool::new(&mut r.ctrl(), "127.0.0.1", port, SYSTEM_RESOLVER, PoolConfig::new(min, max),
+                             Box::new(move |sock, tok, _ctrl| {
+                                 m.set(m.get() + 1);
+                                 Some(Box::new(Client { sock: sock, token: tok, pool: s.clone(), answered: a.clone() }))
+                             })).unwrap();
+    *shared.borrow_mut() = Some(pool.clone());
+    (r, pool)
+}
+
+#[test]
+fn waiters_get_registered_connections() {
+    let answered = Rc::new(Cell::new(0));
+    let made = Rc::new(Cell::new(0));
+    let (mut r, pool) = pool(1, 2, &answered, &made);
+
+    // more checkouts than connections, so some wait for a new connection or a checkin
+    for _ in 0 .. 5 {
+        pool.checkout(&mut r.ctrl(), Box::new(|res, ctrl| {
+            let tok = res.unwrap();
+            // fails for a Context which has not been registered yet
+            ctrl.set_interest(tok, EventSet::readable()).unwrap();
+            ctrl.channel().send((tok, Tendril::from_slice(&b"ping"[..]))).unwrap();
+        }));
+    }
+    while answered.get() < 5 {
+        r.run_once();
+    }
+
+    assert_eq!(made.get(), 2);
+    assert_eq!(pool.size(), (2, 0, 0));
+}
+
+#[test]
+fn self_closed_connection_is_replaced() {
+    let answered = Rc::new(Cell::new(0));
+    let made = Rc::new(Cell::new(0));
+    let (mut r, pool) = pool(1, 1, &answered, &made);
+
+    pool.checkout(&mut r.ctrl(), Box::new(|res, ctrl| {
+        ctrl.channel().send((res.unwrap(), Tendril::from_slice(&b"quit"[..]))).unwrap();
+    }));
+    // long before the first maintenance tick
+    while made.get() < 2 {
+        r.run_once();
+    }
+
+    assert_eq!(pool.size(), (0, 0, 1));
+}
+
+#[test]
+fn connect_which_cannot_start_does_not_hang_the_pool() {
+    let mut r = guarded();
+
+    // no such resolver, so every connect fails before it starts
+    let pool = ConnPool::new(&mut r.ctrl(), "127.0.0.1", 1, ResolverId(99), PoolConfig::new(1, 2),
+                             Box::new(|_sock, _tok, _ctrl| None)).unwrap();
+    assert_eq!(pool.size(), (0, 0, 0));
+
+    let failed = Rc::new(Cell::new(false));
+    let f = failed.clone();
+    pool.checkout(&mut r.ctrl(), Box::new(move |res, _ctrl| f.set(res.is_err())));
+    assert!(failed.get());
+}
@@ -0,0 +1,86 @@
+//! A reconnecting connection keeps its token across connections, so a timer set for
+//! the lost connection's Context must not fire into the Context which replaces it.
+
+extern crate reactor;
+
+mod common;
+
+use std::net::TcpListener;
+use reactor::{ReactorCtrl,
+              ConnResult,
+              Context,
+              EventType,
+              EventSet,
+              Evented,
+              Backoff,
+              StaticResolver};
+
+use reactor::tcp::TcpStream;
+
+use common::{Log, guarded};
+
+struct Conn {
+    sock : TcpStream,
+    id : usize,
+    log : Log
+}
+
+impl Context for Conn {
+    fn on_event(&mut self, ctrl : &mut ReactorCtrl, evt : EventType) {
+        match evt {
+            EventType::Timeout(tag) => {
+                self.log.borrow_mut().push(format!("conn {} timeout {}", self.id, tag));
+                ctrl.shutdown();
+            },
+            EventType::Disconnect => self.log.borrow_mut().push(format!("conn {} lost", self.id)),
+            _ => {}
+        }
+    }
+
+    fn get_evented(&self) -> &Evented {
+        &self.sock as &Evented
+    }
+
+    fn get_interest(&self) -> EventSet {
+        EventSet::readable()
+    }
+}
+
+#[test]
+fn timers_do_not_follow_the_token() {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let port = listener.local_addr().unwrap().port();
+    let log = common::log();
+
+    let mut r = guarded();
+    let mut hosts = StaticResolver::new();
+    hosts.add("peer.test", "127.0.0.1".parse().unwrap());
+    let resolver = r.add_resolver(Box::new(hosts));
+
+    let mut connections = 0;
+    let mut peers = Vec::new();
+    let l = log.clone();
+    r.reconnect("peer.test", port, resolver, Backoff::new(10, 10), Box::new(move |res, ctrl| {
+        match res {
+            ConnResult::Connected(sock, tok, _addr) => {
+                connections += 1;
+                let (peer, _) = listener.accept().unwrap();
+                if connections == 1 {
+                    // due while the second connection is up, if it were not cancelled
+                    ctrl.timeout_conn(200, tok, 1).unwrap();
+                    drop(peer);
+                }
+                else {
+                    ctrl.timeout_conn(400, tok, 2).unwrap();
+                    peers.push(peer);
+                }
+                Some(Box::new(Conn { sock: sock, id: connections, log: l.clone() }))
+            },
+            ConnResult::Failed(err) => panic!("Gave up reconnecting: {}", err)
+        }
+    })).unwrap();
+
+    r.run();
+
+    assert_eq!(*log.borrow(), vec!["conn 1 lost".to_string(), "conn 2 timeout 2".to_string()]);
+}
@@ -0,0 +1,153 @@
+//! Messages and timeouts for Contexts which have gone away, and connections which fail
+//! while they are being established, must be dropped or reported without taking down
+//! the event loop.
+
+extern crate reactor;
+extern crate tendril;
+
+mod common;
+
+use std::net::TcpListener;
+use tendril::Tendril;
+use reactor::{ConnResult,
+              ReactorConfig,
+              Resolver,
+              Resolution,
+              Token,
+              SYSTEM_RESOLVER};
+
+use common::{Recorder, socket, guarded, guarded_with};
+
+#[test]
+fn stale_notify_is_dropped() {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let log = common::log();
+    let mut r = guarded();
+
+    let (sock, _peer) = socket(&listener);
+    let old = r.register(Recorder::new(sock, &log)).unwrap();
+    r.close(old).unwrap();
+
+    // the new Context may well be given the old one's slot
+    let (sock, _peer2) = socket(&listener);
+    let new = r.register(Recorder::new(sock, &log)).unwrap();
+    assert!(old != new);
+
+    let notifier = r.channel();
+    notifier.send((old, Tendril::from_slice(&b"stale"[..]))).unwrap();
+    notifier.send((new, Tendril::from_slice(&b"fresh"[..]))).unwrap();
+    while log.borrow().is_empty() {
+        r.run_once();
+    }
+
+    assert_eq!(*log.borrow(), vec!["fresh".to_string()]);
+    assert_eq!(r.stats().dropped_messages, 1);
+}
+
+#[test]
+fn timeout_for_dead_token_is_dropped() {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let log = common::log();
+    let mut r = guarded();
+
+    let (sock, _peer) = socket(&listener);
+    let tok = r.register(Recorder::new(sock, &log)).unwrap();
+
+    r.timeout_conn(0, Token(usize::max_value() >> 1), 1).unwrap();
+    r.timeout_conn(20, tok, 2).unwrap();
+    while log.borrow().is_empty() {
+        r.run_once();
+    }
+
+    assert_eq!(*log.borrow(), vec!["timeout 2".to_string()]);
+    assert_eq!(r.stats().dropped_messages, 1);
+}
+
+#[test]
+fn timeouts_do_not_outlive_their_context() {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let log = common::log();
+    let mut r = guarded();
+
+    let (sock, _peer) = socket(&listener);
+    let old = r.register(Recorder::new(sock, &log)).unwrap();
+    r.timeout_conn(0, old, 1).unwrap();
+    r.close(old).unwrap();
+
+    let (sock, _peer2) = socket(&listener);
+    let new = r.register(Recorder::new(sock, &log)).unwrap();
+    r.timeout_conn(20, new, 2).unwrap();
+    while log.borrow().is_empty() {
+        r.run_once();
+    }
+
+    assert_eq!(*log.borrow(), vec!["timeout 2".to_string()]);
+}
+
+#[test]
+fn refused_connect_is_reported() {
+    // a port which nothing is listening on, so the connect is reset
+    let port = TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port();
+    let log = common::log();
+    let mut r = guarded();
+
+    let l = log.clone();
+    r.connect("127.0.0.1", port, SYSTEM_RESOLVER, Box::new(move |res, _ctrl| {
+        match res {
+            ConnResult::Connected(..) => panic!("Connected to a closed port"),
+            ConnResult::Failed(err) => l.borrow_mut().push(format!("{:?}", err.kind()))
+        }
+        None
+    })).unwrap();
+
+    while log.borrow().is_empty() {
+        r.run_once();
+    }
+
+    // the loop is still going after the failure, and runs a fresh timeout
+    let l = log.clone();
+    r.timeout(0, Box::new(move |_, _| l.borrow_mut().push("alive".to_string()))).unwrap();
+    while log.borrow().len() < 2 {
+        r.run_once();
+    }
+
+    assert_eq!(*log.borrow(), vec!["ConnectionRefused", "alive"]);
+}
+
+/// Holds on to every lookup without ever answering it
+struct Stalled(Vec<Resolution>);
+
+impl Resolver for Stalled {
+    fn resolve(&mut self, _host : &str, _port : u16, done : Resolution) {
+        self.0.push(done);
+    }
+}
+
+#[test]
+fn connect_timeout_is_timed_out_and_frees_its_slot() {
+    let mut cfg = ReactorConfig::default();
+    cfg.initial_connections = 1;
+    cfg.max_connections = 1;
+    let log = common::log();
+    let mut r = guarded_with(cfg);
+    let resolver = r.add_resolver(Box::new(Stalled(Vec::new())));
+
+    let l = log.clone();
+    r.connect_timeout("peer.test", 1, resolver, 20, Box::new(move |res, _ctrl| {
+        match res {
+            ConnResult::Connected(..) => panic!("Connected without an address"),
+            ConnResult::Failed(err) => l.borrow_mut().push(format!("{:?}", err.kind()))
+        }
+        None
+    })).unwrap();
+    // the only connection slot is taken
+    assert!(r.connect("peer.test", 1, resolver, Box::new(|_, _| None)).is_err());
+
+    while log.borrow().is_empty() {
+        r.run_once();
+    }
+
+    assert_eq!(*log.borrow(), vec!["TimedOut"]);
+    // and given back
+    assert!(r.connect("peer.test", 1, resolver, Box::new(|_, _| None)).is_ok());
+}
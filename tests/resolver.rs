@@ -0,0 +1,56 @@
+//! Connecting by host name through a `StaticResolver`: an unknown host fails with
+//! `NotFound`, and a host with several addresses falls back to the one which answers.
+
+extern crate reactor;
+
+mod common;
+
+use std::cell::RefCell;
+use std::io::ErrorKind;
+use std::net::{SocketAddr, TcpListener};
+use std::rc::Rc;
+use reactor::{ConnResult,
+              StaticResolver};
+
+use common::guarded;
+
+/// Connect to host on port through resolver, returning the peer address connected to
+/// or the kind of error the connection failed with
+fn connect(hosts : StaticResolver, host : &str, port : u16) -> Result<SocketAddr, ErrorKind> {
+    let outcome = Rc::new(RefCell::new(None));
+    let mut r = guarded();
+    let resolver = r.add_resolver(Box::new(hosts));
+
+    let o = outcome.clone();
+    r.connect(host, port, resolver, Box::new(move |res, ctrl| {
+        *o.borrow_mut() = Some(match res {
+            ConnResult::Connected(_sock, _tok, addr) => Ok(addr),
+            ConnResult::Failed(err) => Err(err.kind())
+        });
+        ctrl.shutdown();
+        None
+    })).unwrap();
+
+    r.run();
+    let result = outcome.borrow_mut().take().unwrap();
+    result
+}
+
+#[test]
+fn unknown_host_is_not_found() {
+    let mut hosts = StaticResolver::new();
+    hosts.add("known.test", "127.0.0.1".parse().unwrap());
+    assert_eq!(connect(hosts, "unknown.test", 1), Err(ErrorKind::NotFound));
+}
+
+#[test]
+fn falls_back_to_the_next_address() {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let port = listener.local_addr().unwrap().port();
+
+    // nothing listens on 127.0.0.2, so the first attempt is refused
+    let mut hosts = StaticResolver::new();
+    hosts.add("peer.test", "127.0.0.2".parse().unwrap())
+         .add("peer.test", "127.0.0.1".parse().unwrap());
+    assert_eq!(connect(hosts, "peer.test", port), Ok(listener.local_addr().unwrap()));
+}
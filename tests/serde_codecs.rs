@@ -0,0 +1,137 @@
+//! Round trips typed values through each serde codec over a local TCP pair: the client
+//! sends a frame which cannot be decoded followed by a few values, and the server,
+//! which keeps the connection open through the decode error, echoes the values back.
+#![cfg(any(feature = "json-codec", feature = "bincode-codec", feature = "msgpack-codec"))]
+
+extern crate reactor;
+extern crate tendril;
+
+mod common;
+
+use std::cell::{Cell, RefCell};
+use std::io;
+use std::rc::Rc;
+use tendril::{Tendril, Atomic};
+use tendril::fmt::Bytes;
+use reactor::{ReactorCtrl,
+              ConnResult,
+              Codec,
+              EventType,
+              Framed,
+              FramedContext,
+              FramedHandler,
+              SYSTEM_RESOLVER};
+
+use common::guarded;
+
+type Msg = (u32, String, Vec<u8>);
+
+struct Echo {
+    errors : Rc<Cell<usize>>
+}
+
+impl<C : Codec<In = Msg, Out = Msg>> FramedHandler<C> for Echo {
+    fn on_message(&mut self, conn : &mut Framed<C>, ctrl : &mut ReactorCtrl, msg : Msg) {
+        conn.send(ctrl, msg).unwrap();
+    }
+
+    fn on_decode_error(&mut self, _conn : &mut Framed<C>, _ctrl : &mut ReactorCtrl, _err : io::Error) {
+        self.errors.set(self.errors.get() + 1);
+    }
+}
+
+struct Client {
+    garbage : Tendril<Bytes, Atomic>,
+    sent : Vec<Msg>,
+    got : Rc<RefCell<Vec<Msg>>>
+}
+
+impl<C : Codec<In = Msg, Out = Msg>> FramedHandler<C> for Client {
+    fn on_message(&mut self, _conn : &mut Framed<C>, ctrl : &mut ReactorCtrl, msg : Msg) {
+        self.got.borrow_mut().push(msg);
+        if self.got.borrow().len() == self.sent.len() {
+            ctrl.shutdown();
+        }
+    }
+
+    fn on_event(&mut self, conn : &mut Framed<C>, ctrl : &mut ReactorCtrl, evt : EventType) {
+        if let EventType::Timeout(_) = evt {
+            conn.send_bytes(ctrl, self.garbage.clone()).unwrap();
+            for msg in self.sent.iter() {
+                conn.send(ctrl, msg.clone()).unwrap();
+            }
+        }
+    }
+}
+
+fn messages() -> Vec<Msg> {
+    vec![(1, "one".to_string(), vec![]),
+         (2, "two\nlines".to_string(), vec![0, 10, 255]),
+         (3, String::new(), (0 .. 200).collect())]
+}
+
+fn round_trip<C, F>(garbage : &[u8], codec : F)
+    where C : Codec<In = Msg, Out = Msg> + 'static,
+          F : Fn() -> C + 'static
+{
+    let codec = Rc::new(codec);
+    let errors = Rc::new(Cell::new(0));
+    let got = Rc::new(RefCell::new(Vec::new()));
+    let mut r = guarded();
+
+    let (c, e) = (codec.clone(), errors.clone());
+    let listener = r.listen(("127.0.0.1", 0), Box::new(move |res, _ctrl| {
+        match res {
+            ConnResult::Connected(sock, _tok, _addr) => {
+                Some(Box::new(FramedContext::new(sock, c(), Echo { errors: e.clone() })))
+            },
+            ConnResult::Failed(err) => panic!("Failed to accept: {}", err)
+        }
+    })).unwrap();
+    let port = r.local_addr(listener).unwrap().port();
+
+    let client = Client {
+        garbage: Tendril::from_slice(garbage),
+        sent: messages(),
+        got: got.clone()
+    };
+    let mut client = Some(client);
+    r.connect("127.0.0.1", port, SYSTEM_RESOLVER, Box::new(move |res, ctrl| {
+        match res {
+            ConnResult::Connected(sock, tok, _addr) => {
+                ctrl.timeout_conn(0, tok, 0).unwrap();
+                Some(Box::new(FramedContext::new(sock, codec(), client.take().unwrap())))
+            },
+            ConnResult::Failed(err) => panic!("Failed to connect: {}", err)
+        }
+    })).unwrap();
+
+    r.run();
+
+    assert_eq!(errors.get(), 1);
+    assert_eq!(*got.borrow(), messages());
+}
+
+/// A frame of the length prefixed codecs holding bytes which are not a Msg
+#[cfg(any(feature = "bincode-codec", feature = "msgpack-codec"))]
+fn bad_frame() -> Vec<u8> {
+    vec![0, 0, 0, 1, 0xc1]
+}
+
+#[cfg(feature = "json-codec")]
+#[test]
+fn json_round_trip() {
+    round_trip(b"{not json\n", reactor::JsonCodec::<Msg, Msg>::new);
+}
+
+#[cfg(feature = "bincode-codec")]
+#[test]
+fn bincode_round_trip() {
+    round_trip(&bad_frame(), reactor::BincodeCodec::<Msg, Msg>::new);
+}
+
+#[cfg(feature = "msgpack-codec")]
+#[test]
+fn msgpack_round_trip() {
+    round_trip(&bad_frame(), reactor::MsgPackCodec::<Msg, Msg>::new);
+}